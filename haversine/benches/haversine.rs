@@ -0,0 +1,94 @@
+use pap::{parse, repetition::RepetitionTester, sum_haversines, Pair, Pairs, EARTH_RADIUS_KM};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::ThreadPoolBuilder;
+use std::{env, hint::black_box, mem, time::Duration};
+
+const PAIRS: usize = 100_000;
+
+const TRY_FOR: Duration = Duration::from_secs(3);
+
+// The same fixed set of pairs every run so numbers are comparable between changes
+fn input() -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let pairs = Pairs {
+        pairs: (0..PAIRS)
+            .map(|_| Pair {
+                x0: rng.gen_range(-180.0..180.0),
+                y0: rng.gen_range(-90.0..90.0),
+                x1: rng.gen_range(-180.0..180.0),
+                y1: rng.gen_range(-90.0..90.0),
+            })
+            .collect(),
+    };
+
+    serde_json::to_vec(&pairs).unwrap()
+}
+
+// cargo bench passes --bench, without it (cargo test --benches) every bench runs once as a smoke
+// test
+fn bench(name: &'static str, bytes: usize, try_for: Duration, mut f: impl FnMut()) -> Duration {
+    let mut tester = RepetitionTester::new(name, bytes as u64, try_for);
+
+    loop {
+        tester.begin_time();
+        f();
+        tester.end_time();
+
+        if !tester.is_testing() {
+            break;
+        }
+    }
+
+    tester.print();
+    tester.min()
+}
+
+fn print_speedup(name: &str, serial: Duration, parallel: Duration) {
+    println!(
+        "{name} speedup: {:.2}x",
+        serial.as_secs_f64() / parallel.as_secs_f64()
+    );
+}
+
+fn main() {
+    let try_for = if env::args().any(|arg| arg == "--bench") {
+        TRY_FOR
+    } else {
+        Duration::ZERO
+    };
+
+    let input = input();
+    let chunks = rayon::current_num_threads() * 4;
+
+    let serial = bench("Parse serial", input.len(), try_for, || {
+        black_box(parse(&input, 1).unwrap());
+    });
+    let parallel = bench("Parse parallel", input.len(), try_for, || {
+        black_box(parse(&input, chunks).unwrap());
+    });
+    print_speedup("Parse", serial, parallel);
+
+    let pairs = parse(&input, chunks).unwrap().pairs;
+    let bytes = mem::size_of_val(pairs.as_slice());
+
+    // The same compensated chunked sum main runs, on one thread and on all of them
+    let one_thread = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+    let serial = bench("Math serial", bytes, try_for, || {
+        black_box(one_thread.install(|| sum_haversines(&pairs, EARTH_RADIUS_KM)));
+    });
+    let parallel = bench("Math parallel", bytes, try_for, || {
+        black_box(sum_haversines(&pairs, EARTH_RADIUS_KM));
+    });
+    print_speedup("Math", serial, parallel);
+
+    // Scalar against 8 at a time, both on one thread
+    #[cfg(target_arch = "x86_64")]
+    {
+        let simd = bench("Math simd", bytes, try_for, || {
+            black_box(one_thread.install(|| pap::simd::sum_haversines(&pairs, EARTH_RADIUS_KM)));
+        });
+        print_speedup("Simd", serial, simd);
+    }
+}
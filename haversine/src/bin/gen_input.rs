@@ -1,31 +1,429 @@
-use std::fs;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    process,
+};
 
-use serde::{Deserialize, Serialize};
+use clap::{Parser, ValueEnum};
+use pap::{
+    answer_path, haversine_of_degrees, mmap::Mmap, parse, parse_and_fold, parse_binary,
+    parse_ndjson, parse_ndjson_and_fold, progress, sum_distances, CompensatedSum, Pair,
+    EARTH_RADIUS_KM,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-#[derive(Default, Debug, Serialize, Deserialize)]
-pub struct Pair {
-    pub x0: f32,
-    pub y0: f32,
-    pub x1: f32,
-    pub y1: f32,
+#[derive(Copy, Clone, ValueEnum)]
+enum OutputFormat {
+    Json,
+    // Flat little endian f64 [x0, y0, x1, y1] records
+    Binary,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize)]
-pub struct Pairs {
-    pub pairs: Vec<Pair>,
+impl OutputFormat {
+    fn path(self, ndjson: bool) -> &'static str {
+        match self {
+            OutputFormat::Json if ndjson => "input.ndjson",
+            OutputFormat::Json => "input.json",
+            OutputFormat::Binary => "input.f64",
+        }
+    }
+}
+
+impl Args {
+    fn path(&self) -> &str {
+        self.output
+            .as_deref()
+            .unwrap_or(self.format.path(self.ndjson))
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Mode {
+    // Points scattered evenly over the whole globe
+    Uniform,
+    // Both points of a pair are taken from around one of a set of random centers
+    Cluster,
+}
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Format to write, json writes input.json and binary writes input.f64
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+
+    /// File to write instead of the format's default, the answer goes next to it in
+    /// <output>.answer
+    #[arg(long, short)]
+    output: Option<String>,
+
+    /// Write json as one pair object per line to input.ndjson instead of a single array
+    #[arg(long)]
+    ndjson: bool,
+
+    /// Round every coordinate to this many decimal digits and write them with exactly that many
+    #[arg(long, value_name = "N")]
+    precision: Option<usize>,
+
+    /// How points are distributed
+    #[arg(long, value_enum, default_value_t = Mode::Uniform)]
+    mode: Mode,
+
+    /// Number of clusters to pick points from in cluster mode
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..))]
+    clusters: u32,
+
+    /// Seed for the random number generator, the same seed always generates the same pairs
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Parse the written file back and check it holds exactly the generated pairs
+    #[arg(long)]
+    check: bool,
+
+    /// Add the pairs to the end of the existing file instead of replacing it, the answer then
+    /// holds the average of all of them
+    #[arg(long)]
+    append: bool,
+
+    /// Print how many pairs have been generated every second
+    #[arg(long)]
+    progress: bool,
+
+    /// Number of pairs to generate
+    #[arg(long, default_value_t = 10_000_000)]
+    count: usize,
+}
+
+const MAX_LONGITUDE: f64 = 180.0;
+const MAX_LATITUDE: f64 = 90.0;
+
+// How far in degrees a point may be from its cluster center on each axis
+const CLUSTER_SPREAD: f64 = 10.0;
+
+fn random_longitude(rng: &mut StdRng) -> f64 {
+    rng.gen_range(-MAX_LONGITUDE..MAX_LONGITUDE)
+}
+
+fn random_latitude(rng: &mut StdRng) -> f64 {
+    rng.gen_range(-MAX_LATITUDE..MAX_LATITUDE)
+}
+
+// Longitudes wrap around the antimeridian while latitudes stop at the poles
+fn near_longitude(rng: &mut StdRng, center: f64) -> f64 {
+    let x = center + rng.gen_range(-CLUSTER_SPREAD..CLUSTER_SPREAD);
+    (x + MAX_LONGITUDE).rem_euclid(2.0 * MAX_LONGITUDE) - MAX_LONGITUDE
+}
+
+fn near_latitude(rng: &mut StdRng, center: f64) -> f64 {
+    let y = center + rng.gen_range(-CLUSTER_SPREAD..CLUSTER_SPREAD);
+    y.clamp(-MAX_LATITUDE, MAX_LATITUDE)
+}
+
+// Rounds through the same formatting the json is written with, so the pairs, the answer and the
+// file all agree
+fn round(value: f64, precision: Option<usize>) -> f64 {
+    match precision {
+        Some(precision) => format!("{value:.precision$}").parse().unwrap(),
+        None => value,
+    }
+}
+
+// Writes each pair as it's generated, so memory use doesn't grow with the count. Json is laid out
+// like serde_json writes Pairs
+struct Writer {
+    file: BufWriter<File>,
+    format: OutputFormat,
+    ndjson: bool,
+    precision: Option<usize>,
+    // Whether the json array already holds a pair, so the next one needs a comma before it
+    separate: bool,
+}
+
+// Bytes at the end of a json file searched for the end of the pairs array when appending
+const JSON_TAIL: u64 = 4096;
+
+impl Writer {
+    fn create(args: &Args) -> io::Result<Writer> {
+        let mut writer = Writer {
+            file: BufWriter::new(File::create(args.path())?),
+            format: args.format,
+            ndjson: args.ndjson,
+            precision: args.precision,
+            separate: false,
+        };
+        if writer.is_array() {
+            writer.file.write_all(b"{\"pairs\":[")?;
+        }
+        Ok(writer)
+    }
+
+    // Continues an existing file, ndjson and binary just grow while the json array is reopened by
+    // cutting off the ]} closing it
+    fn append(args: &Args) -> io::Result<Writer> {
+        let path = args.path();
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len();
+
+        let mut separate = false;
+        if matches!(args.format, OutputFormat::Json) && !args.ndjson {
+            let start = len.saturating_sub(JSON_TAIL);
+            let mut tail = Vec::new();
+            file.seek(SeekFrom::Start(start))?;
+            file.read_to_end(&mut tail)?;
+
+            let mut end = tail.len();
+            for token in [b'}', b']'] {
+                end = tail[..end].trim_ascii_end().len();
+                if end == 0 || tail[end - 1] != token {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{path} doesn't end with the pairs array"),
+                    ));
+                }
+                end -= 1;
+            }
+
+            separate = tail[..end].trim_ascii_end().last() != Some(&b'[');
+            file.set_len(start + end as u64)?;
+        }
+
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(Writer {
+            file: BufWriter::new(file),
+            format: args.format,
+            ndjson: args.ndjson,
+            precision: args.precision,
+            separate,
+        })
+    }
+
+    fn is_array(&self) -> bool {
+        matches!(self.format, OutputFormat::Json) && !self.ndjson
+    }
+
+    // serde_json's shortest digits that parse back to the same f64 unless the digits are asked for
+    fn write_number(&mut self, value: f64) -> io::Result<()> {
+        match self.precision {
+            Some(precision) => write!(self.file, "{value:.precision$}"),
+            None => serde_json::to_writer(&mut self.file, &value).map_err(io::Error::from),
+        }
+    }
+
+    fn write(&mut self, pair: &Pair) -> io::Result<()> {
+        let values = [pair.x0, pair.y0, pair.x1, pair.y1];
+
+        if let OutputFormat::Binary = self.format {
+            for value in values {
+                self.file.write_all(&value.to_le_bytes())?;
+            }
+            return Ok(());
+        }
+
+        if self.separate {
+            self.file.write_all(b",")?;
+        }
+        self.separate = self.is_array();
+
+        for (key, value) in ["{\"x0\":", ",\"y0\":", ",\"x1\":", ",\"y1\":"]
+            .iter()
+            .zip(values)
+        {
+            self.file.write_all(key.as_bytes())?;
+            self.write_number(value)?;
+        }
+        self.file.write_all(b"}")?;
+
+        if self.ndjson {
+            self.file.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if self.is_array() {
+            self.file.write_all(b"]}")?;
+        }
+        self.file.flush()
+    }
+}
+
+// Sum of the distances and number of pairs in the file being appended to, nothing when there's
+// no file yet
+fn existing(path: &str, format: OutputFormat, ndjson: bool) -> (f64, usize) {
+    let input = match Mmap::open(path) {
+        Ok(input) => input,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return (0.0, 0),
+        Err(e) => {
+            eprintln!("Unable to read {path}: {e}");
+            process::exit(1);
+        }
+    };
+
+    let distance = |p: &Pair| haversine_of_degrees(p, EARTH_RADIUS_KM);
+    let chunks = rayon::current_num_threads() * 4;
+    let existing = match format {
+        OutputFormat::Json if ndjson => parse_ndjson_and_fold(&input, chunks, distance),
+        OutputFormat::Json => parse_and_fold(&input, chunks, distance),
+        OutputFormat::Binary => parse_binary(&input)
+            .map(|pairs| (sum_distances(&pairs.pairs, distance), pairs.pairs.len())),
+    };
+
+    match existing {
+        Ok(existing) => existing,
+        Err(e) => {
+            eprintln!("Unable to append to {path}: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+// Most mismatching pairs printed before only counting the rest
+const MAX_REPORTED_MISMATCHES: usize = 10;
+
+// Both formats store f64 and serde_json writes the shortest digits that parse back to the same
+// f64, so anything short of bit for bit equality is a bug in the writer or the parser
+fn check(path: &str, format: OutputFormat, ndjson: bool, pairs: &[Pair], previous: usize) {
+    let input = fs::read(path).unwrap();
+    let parsed = match format {
+        OutputFormat::Json if ndjson => parse_ndjson(&input, rayon::current_num_threads() * 4),
+        OutputFormat::Json => parse(&input, rayon::current_num_threads() * 4),
+        OutputFormat::Binary => parse_binary(&input),
+    };
+    let parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Check failed, unable to parse {path}: {e}");
+            process::exit(1);
+        }
+    };
+
+    let bits = |p: &Pair| [p.x0, p.y0, p.x1, p.y1].map(f64::to_bits);
+    let mismatches = pairs
+        .iter()
+        .zip(parsed.pairs.get(previous..).unwrap_or_default())
+        .enumerate()
+        .filter(|(_, (a, b))| bits(a) != bits(b))
+        .collect::<Vec<_>>();
+
+    for (index, (generated, parsed)) in mismatches.iter().take(MAX_REPORTED_MISMATCHES) {
+        let index = previous + index;
+        eprintln!("Pair {index} differs: generated {generated:?}, parsed {parsed:?}");
+    }
+    if mismatches.len() > MAX_REPORTED_MISMATCHES {
+        eprintln!(
+            "and {} more differing pairs",
+            mismatches.len() - MAX_REPORTED_MISMATCHES
+        );
+    }
+
+    if parsed.pairs.len() != previous + pairs.len() {
+        eprintln!(
+            "Expected {} pairs, parsed {}",
+            previous + pairs.len(),
+            parsed.pairs.len()
+        );
+        process::exit(1);
+    }
+    if !mismatches.is_empty() {
+        process::exit(1);
+    }
+
+    println!("Checked {} pairs", pairs.len());
 }
 
 fn main() {
-    let mut pairs = Pairs::default();
+    let args = Args::parse();
+
+    let mut rng = match args.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    let centers = (0..args.clusters)
+        .map(|_| (random_longitude(&mut rng), random_latitude(&mut rng)))
+        .collect::<Vec<_>>();
 
-    for _ in 0..10_000_000 {
-        pairs.pairs.push(Pair {
-            x0: rand::random(),
-            y0: rand::random(),
-            x1: rand::random(),
-            y1: rand::random(),
-        });
+    let (previous_sum, previous) = if args.append {
+        existing(args.path(), args.format, args.ndjson)
+    } else {
+        (0.0, 0)
+    };
+
+    let path = args.path();
+    let writer = if args.append && previous > 0 {
+        Writer::append(&args)
+    } else {
+        Writer::create(&args)
+    };
+    let mut writer = match writer {
+        Ok(writer) => writer,
+        Err(e) => {
+            eprintln!("Unable to write {path}: {e}");
+            process::exit(1);
+        }
+    };
+
+    // Only --check needs the pairs once they've been written
+    let mut generated = Vec::with_capacity(if args.check { args.count } else { 0 });
+    let mut sum = CompensatedSum::default();
+    sum.add(previous_sum);
+
+    let reporter = args
+        .progress
+        .then(|| progress::Reporter::start("Generated"));
+    for i in 0..args.count {
+        let pair = match args.mode {
+            Mode::Uniform => Pair {
+                x0: random_longitude(&mut rng),
+                y0: random_latitude(&mut rng),
+                x1: random_longitude(&mut rng),
+                y1: random_latitude(&mut rng),
+            },
+            Mode::Cluster => {
+                let (x, y) = centers[rng.gen_range(0..centers.len())];
+                Pair {
+                    x0: near_longitude(&mut rng, x),
+                    y0: near_latitude(&mut rng, y),
+                    x1: near_longitude(&mut rng, x),
+                    y1: near_latitude(&mut rng, y),
+                }
+            }
+        };
+        let pair = Pair {
+            x0: round(pair.x0, args.precision),
+            y0: round(pair.y0, args.precision),
+            x1: round(pair.x1, args.precision),
+            y1: round(pair.y1, args.precision),
+        };
+        sum.add(haversine_of_degrees(&pair, EARTH_RADIUS_KM));
+        writer.write(&pair).unwrap();
+        if args.check {
+            generated.push(pair);
+        }
+
+        if (i + 1) % progress::BATCH == 0 {
+            progress::add(progress::BATCH);
+        }
+    }
+
+    drop(reporter);
+    writer.finish().unwrap();
+
+    let average = sum.total() / (previous + args.count) as f64;
+
+    let answer_path = answer_path(path);
+    if let Err(e) = fs::write(&answer_path, format!("{average}\n")) {
+        eprintln!("Unable to write {answer_path}: {e}");
+        process::exit(1);
     }
+    if previous > 0 {
+        println!("Appended {} pairs to {previous}", args.count);
+    }
+    println!("Average: {average}");
 
-    fs::write("input.json", serde_json::to_string(&pairs).unwrap()).unwrap();
+    if args.check {
+        check(path, args.format, args.ndjson, &generated, previous);
+    }
 }
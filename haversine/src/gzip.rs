@@ -0,0 +1,569 @@
+// Just enough gzip to read back files compressed by gzip or zlib, inflate as described in
+// RFC 1951 with the member framing from RFC 1952. Every member is decompressed into one buffer
+// since the parallel parser wants the whole input as a slice
+
+use std::fmt::Display;
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const DEFLATE: u8 = 8;
+
+const FLAG_HCRC: u8 = 1 << 1;
+const FLAG_EXTRA: u8 = 1 << 2;
+const FLAG_NAME: u8 = 1 << 3;
+const FLAG_COMMENT: u8 = 1 << 4;
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Order the code length code lengths are stored in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Debug)]
+pub struct GzipError {
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl Display for GzipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at compressed byte {}", self.message, self.offset)
+    }
+}
+
+pub fn is_gzip(input: &[u8]) -> bool {
+    input.starts_with(&MAGIC)
+}
+
+// Deflate packs bits starting from the least significant bit of each byte
+struct Bits<'a> {
+    input: &'a [u8],
+    index: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn error(&self, message: &'static str) -> GzipError {
+        GzipError {
+            offset: self.index - self.count as usize / 8,
+            message,
+        }
+    }
+
+    fn refill(&mut self) {
+        while self.count <= 56 && self.index < self.input.len() {
+            self.buffer |= (self.input[self.index] as u64) << self.count;
+            self.index += 1;
+            self.count += 8;
+        }
+    }
+
+    // Past the end of the input the missing bits read as zero, consume catches running out
+    fn peek(&mut self, n: u32) -> u32 {
+        if self.count < n {
+            self.refill();
+        }
+        (self.buffer & ((1 << n) - 1)) as u32
+    }
+
+    fn consume(&mut self, n: u32) -> Result<(), GzipError> {
+        if self.count < n {
+            return Err(self.error("Unexpected end of compressed data"));
+        }
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, GzipError> {
+        let value = self.peek(n);
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    // Drops the rest of the current byte and hands back the bytes buffered past it
+    fn align_to_byte(&mut self) {
+        self.index -= self.count as usize / 8;
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// Every MAX_BITS wide bit pattern maps to the symbol whose code it starts with, so decoding is
+// a single lookup. Entries are symbol << 4 | code length with a length of 0 for unused patterns
+struct Huffman {
+    table: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, &'static str> {
+        let mut counts = [0u32; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut next_code = [0u32; MAX_BITS + 1];
+        let mut code = 0;
+        for length in 1..=MAX_BITS {
+            code = (code + counts[length - 1]) << 1;
+            if code + counts[length] > 1 << length {
+                return Err("Over subscribed huffman code");
+            }
+            next_code[length] = code;
+        }
+
+        let mut table = vec![0; 1 << MAX_BITS];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let length = length as usize;
+            let code = next_code[length];
+            next_code[length] += 1;
+
+            // Codes are stored most significant bit first, so reverse them to match the reader
+            let reversed = code.reverse_bits() >> (32 - length);
+            for pattern in (reversed as usize..1 << MAX_BITS).step_by(1 << length) {
+                table[pattern] = (symbol << 4 | length) as u16;
+            }
+        }
+
+        Ok(Huffman { table })
+    }
+
+    fn fixed() -> (Huffman, Huffman) {
+        let mut lengths = [0; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+
+        (
+            Huffman::new(&lengths).unwrap(),
+            Huffman::new(&[5; 30]).unwrap(),
+        )
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<usize, GzipError> {
+        let entry = self.table[bits.peek(MAX_BITS as u32) as usize];
+        let length = (entry & 0xf) as u32;
+        if length == 0 {
+            return Err(bits.error("Invalid huffman code"));
+        }
+        bits.consume(length)?;
+        Ok((entry >> 4) as usize)
+    }
+}
+
+fn read_dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), GzipError> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+
+    let mut lengths = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[index] = bits.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths).map_err(|e| bits.error(e))?;
+
+    let mut lengths = vec![0u8; literals + distances];
+    let mut index = 0;
+    while index < lengths.len() {
+        let (value, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(bits.error("Repeated code length without a previous one"));
+                }
+                (lengths[index - 1], 3 + bits.bits(2)? as usize)
+            }
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+
+        if index + repeat > lengths.len() {
+            return Err(bits.error("Code lengths run past the end of the table"));
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+
+    if lengths[256] == 0 {
+        return Err(bits.error("Missing end of block code"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literals]).map_err(|e| bits.error(e))?,
+        Huffman::new(&lengths[literals..]).map_err(|e| bits.error(e))?,
+    ))
+}
+
+fn inflate_block(
+    bits: &mut Bits,
+    output: &mut Vec<u8>,
+    literal_code: &Huffman,
+    distance_code: &Huffman,
+) -> Result<(), GzipError> {
+    loop {
+        let symbol = literal_code.decode(bits)?;
+
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(bits.error("Invalid length code"));
+        }
+        let length =
+            LENGTH_BASE[symbol] as usize + bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+        let symbol = distance_code.decode(bits)?;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(bits.error("Invalid distance code"));
+        }
+        let distance =
+            DISTANCE_BASE[symbol] as usize + bits.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+
+        if distance > output.len() {
+            return Err(bits.error("Distance reaches back before the start of the output"));
+        }
+
+        let start = output.len() - distance;
+        if distance >= length {
+            output.extend_from_within(start..start + length);
+        } else {
+            // The copy overlaps what it writes, repeating the last distance bytes
+            for i in 0..length {
+                output.push(output[start + i]);
+            }
+        }
+    }
+}
+
+fn inflate(bits: &mut Bits, output: &mut Vec<u8>) -> Result<(), GzipError> {
+    loop {
+        let last = bits.bits(1)? == 1;
+
+        match bits.bits(2)? {
+            0 => {
+                bits.align_to_byte();
+                let start = bits.index;
+                let header = bits.input.get(start..start + 4).ok_or(GzipError {
+                    offset: start,
+                    message: "Unexpected end of compressed data",
+                })?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err(GzipError {
+                        offset: start,
+                        message: "Stored block length doesn't match its complement",
+                    });
+                }
+                let data =
+                    bits.input
+                        .get(start + 4..start + 4 + len as usize)
+                        .ok_or(GzipError {
+                            offset: start + 4,
+                            message: "Unexpected end of compressed data",
+                        })?;
+                output.extend_from_slice(data);
+                bits.index = start + 4 + len as usize;
+            }
+            1 => {
+                let (literal_code, distance_code) = Huffman::fixed();
+                inflate_block(bits, output, &literal_code, &distance_code)?;
+            }
+            2 => {
+                let (literal_code, distance_code) = read_dynamic_codes(bits)?;
+                inflate_block(bits, output, &literal_code, &distance_code)?;
+            }
+            _ => return Err(bits.error("Invalid block type")),
+        }
+
+        if last {
+            bits.align_to_byte();
+            return Ok(());
+        }
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let table: [u32; 256] = std::array::from_fn(|i| {
+        (0..8).fold(i as u32, |c, _| {
+            if c & 1 == 1 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            }
+        })
+    });
+
+    !data.iter().fold(!0, |c, &byte| {
+        table[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+// Skips a member's header and returns where its compressed data starts
+fn read_header(input: &[u8], start: usize) -> Result<usize, GzipError> {
+    let error = |offset, message| GzipError { offset, message };
+
+    let header = input
+        .get(start..start + 10)
+        .ok_or(error(start, "Truncated gzip header"))?;
+    if header[..2] != MAGIC {
+        return Err(error(start, "Missing gzip magic bytes"));
+    }
+    if header[2] != DEFLATE {
+        return Err(error(start + 2, "Unsupported compression method"));
+    }
+    let flags = header[3];
+    let mut index = start + 10;
+
+    if flags & FLAG_EXTRA != 0 {
+        let len = input
+            .get(index..index + 2)
+            .ok_or(error(index, "Truncated gzip header"))?;
+        index += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            let end = input
+                .get(index..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+                .ok_or(error(index, "Truncated gzip header"))?;
+            index += end + 1;
+        }
+    }
+    if flags & FLAG_HCRC != 0 {
+        index += 2;
+    }
+
+    if index > input.len() {
+        return Err(error(start, "Truncated gzip header"));
+    }
+    Ok(index)
+}
+
+// Generated JSON compresses a few times over, far below this
+const MAX_RATIO: usize = 16;
+
+// The trailer of the last member holds its size mod 2^32, right for the usual single member. It
+// isn't checked until the end, so a corrupt or hostile one is capped to what the input could hold
+fn initial_capacity(input: &[u8]) -> usize {
+    match input.len().checked_sub(4) {
+        Some(end) => (u32::from_le_bytes(input[end..].try_into().unwrap()) as usize)
+            .min(input.len().saturating_mul(MAX_RATIO)),
+        None => 0,
+    }
+}
+
+// Decompresses every member of a gzip file one after the other, checking each one's crc and size
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, GzipError> {
+    profile_block!("gunzip");
+
+    let mut output = Vec::with_capacity(initial_capacity(input));
+
+    let mut start = 0;
+    while start < input.len() {
+        let mut bits = Bits {
+            input,
+            index: read_header(input, start)?,
+            buffer: 0,
+            count: 0,
+        };
+
+        let member = output.len();
+        inflate(&mut bits, &mut output)?;
+
+        let trailer = input.get(bits.index..bits.index + 8).ok_or(GzipError {
+            offset: bits.index,
+            message: "Truncated gzip trailer",
+        })?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+
+        if size != (output.len() - member) as u32 {
+            return Err(GzipError {
+                offset: bits.index + 4,
+                message: "Decompressed size doesn't match the trailer",
+            });
+        }
+        if crc != crc32(&output[member..]) {
+            return Err(GzipError {
+                offset: bits.index,
+                message: "Crc doesn't match the trailer",
+            });
+        }
+
+        start = bits.index + 8;
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Raw deflate from zlib with Z_FIXED for "hello, hello, hello world"
+    const FIXED: &str = "cb48cdc9c9d751c840a214caf38b725200";
+
+    // Raw deflate from zlib at level 9 for twelve_pairs(), which picks dynamic codes
+    const DYNAMIC: &str = "7dd03b0a80301084e1bb6c1d439e1a7315b1b0b4136d14c9dd0d08a38563b5c3\
+                           f255ff29cb34af9be4e194dd48363a2a39ea688c7675ee56b2af9f7a4251b7b1\
+                           30961a07e3a8f1309e9a0013a88930919a16a6a5a683e9a84930899a1ea6e70d\
+                           9fd0f6a7f42bf547ebb15c";
+
+    #[test]
+    fn trailer_size_is_capped() {
+        assert_eq!(initial_capacity(&[]), 0);
+        // An honest trailer, and one claiming 4GB out of 8 bytes
+        assert_eq!(initial_capacity(&[0, 0, 0, 0, 100, 0, 0, 0]), 100);
+        assert_eq!(
+            initial_capacity(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]),
+            8 * MAX_RATIO
+        );
+        assert!(decompress(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn twelve_pairs() -> Vec<u8> {
+        let pairs = (0..12)
+            .map(|i| format!(r#"{{"x0":{i}.5,"y0":-{i}.25,"x1":3,"y1":4}}"#))
+            .collect::<Vec<_>>();
+        format!(r#"{{"pairs":[{}]}}"#, pairs.join(",")).into_bytes()
+    }
+
+    // A single stored block holding data
+    fn stored(data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut deflate = vec![0b001];
+        deflate.extend(len.to_le_bytes());
+        deflate.extend((!len).to_le_bytes());
+        deflate.extend(data);
+        deflate
+    }
+
+    // A gzip member around the deflate stream, with the trailer for data
+    fn member(deflate: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut member = vec![0x1f, 0x8b, DEFLATE, 0, 0, 0, 0, 0, 0, 0xff];
+        member.extend(deflate);
+        member.extend(crc32(data).to_le_bytes());
+        member.extend((data.len() as u32).to_le_bytes());
+        member
+    }
+
+    fn message(input: &[u8]) -> &'static str {
+        decompress(input).unwrap_err().message
+    }
+
+    #[test]
+    fn crc_of_known_data() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn stored_block() {
+        let data = b"{\"pairs\":[]}";
+        assert_eq!(decompress(&member(&stored(data), data)).unwrap(), data);
+    }
+
+    #[test]
+    fn fixed_block() {
+        let data = b"hello, hello, hello world";
+        assert_eq!(decompress(&member(&hex(FIXED), data)).unwrap(), data);
+    }
+
+    #[test]
+    fn dynamic_block() {
+        let data = twelve_pairs();
+        assert_eq!(decompress(&member(&hex(DYNAMIC), &data)).unwrap(), data);
+    }
+
+    #[test]
+    fn members_follow_each_other() {
+        let mut input = member(&stored(b"hello, "), b"hello, ");
+
+        // With a file name in the header
+        let mut named = member(&hex(FIXED), b"hello, hello, hello world");
+        named[3] = FLAG_NAME;
+        named.splice(10..10, b"input.json\0".iter().copied());
+        input.extend(named);
+
+        assert_eq!(
+            decompress(&input).unwrap(),
+            b"hello, hello, hello, hello world"
+        );
+    }
+
+    #[test]
+    fn bad_crc_is_rejected() {
+        let data = b"hello, hello, hello world";
+        let mut input = member(&hex(FIXED), data);
+        let crc = input.len() - 8;
+        input[crc] ^= 1;
+
+        assert_eq!(message(&input), "Crc doesn't match the trailer");
+    }
+
+    #[test]
+    fn bad_size_is_rejected() {
+        let data = twelve_pairs();
+        let mut input = member(&hex(DYNAMIC), &data);
+        let size = input.len() - 4;
+        input[size] ^= 1;
+
+        assert_eq!(
+            message(&input),
+            "Decompressed size doesn't match the trailer"
+        );
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let data = twelve_pairs();
+        let input = member(&hex(DYNAMIC), &data);
+
+        for end in 1..input.len() {
+            assert!(decompress(&input[..end]).is_err(), "{end} bytes decoded");
+        }
+    }
+
+    #[test]
+    fn garbage_after_a_member_is_rejected() {
+        let mut input = member(&stored(b"{}"), b"{}");
+        input.extend(b"{}");
+
+        assert_eq!(message(&input), "Truncated gzip header");
+    }
+}
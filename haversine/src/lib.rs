@@ -0,0 +1,1119 @@
+#[macro_use]
+pub mod profiler;
+
+pub mod gzip;
+pub mod mmap;
+pub mod progress;
+pub mod repetition;
+#[cfg(target_arch = "x86_64")]
+pub mod simd;
+pub mod timer;
+
+use rayon::{
+    prelude::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSlice,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str};
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct Pair {
+    pub x0: f64,
+    pub y0: f64,
+    pub x1: f64,
+    pub y1: f64,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct Pairs {
+    pub pairs: Vec<Pair>,
+}
+
+pub const EARTH_RADIUS_KM: f64 = 6371.0;
+
+// Where gen_input writes the expected average for the data at path, next to it with the
+// extension kept so datasets of different formats don't share one. Gzip is looked through, as
+// the answer is the same for the compressed file
+pub fn answer_path(path: &str) -> String {
+    format!("{}.answer", path.strip_suffix(".gz").unwrap_or(path))
+}
+
+// Distance along the surface of a sphere of the given radius, in the unit of the radius
+pub fn haversine_of_degrees(p: &Pair, radius: f64) -> f64 {
+    profile_block!("haversine");
+
+    let dy = (p.y1 - p.y0).to_radians();
+    let dx = (p.x1 - p.x0).to_radians();
+    let y0 = p.y0.to_radians();
+    let y1 = p.y1.to_radians();
+
+    let sin_dy = f64::sin(dy / 2.0);
+    let sin_dx = f64::sin(dx / 2.0);
+
+    let root_term = (sin_dy * sin_dy) + f64::cos(y0) * f64::cos(y1) * (sin_dx * sin_dx);
+    2.0 * radius * f64::asin(f64::sqrt(root_term))
+}
+
+// Neumaier's compensated summation, keeping the rounding error of every addition apart so it
+// can be added back at the end instead of building up over millions of terms
+#[derive(Clone, Copy, Default)]
+pub struct CompensatedSum {
+    sum: f64,
+    compensation: f64,
+}
+
+impl CompensatedSum {
+    pub fn add(&mut self, value: f64) {
+        let sum = self.sum + value;
+        self.compensation += if self.sum.abs() >= value.abs() {
+            (self.sum - sum) + value
+        } else {
+            (value - sum) + self.sum
+        };
+        self.sum = sum;
+    }
+
+    pub fn merge(&mut self, other: CompensatedSum) {
+        self.add(other.sum);
+        self.compensation += other.compensation;
+    }
+
+    pub fn total(self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+// Pairs per chunk summed on one thread. The chunks don't depend on the number of threads, so
+// neither does the result
+const SUM_CHUNK_SIZE: usize = 1 << 14;
+
+// Compensated sum of the distance of every pair, the same on every run and any number of threads
+pub fn sum_distances(pairs: &[Pair], distance: impl Fn(&Pair) -> f64 + Sync) -> f64 {
+    sum_distances_with_min_len(pairs, 1, distance)
+}
+
+// Like sum_distances with every rayon task summing at least min_len chunks, which trades
+// balancing the threads for less overhead per task without changing the result
+pub fn sum_distances_with_min_len(
+    pairs: &[Pair],
+    min_len: usize,
+    distance: impl Fn(&Pair) -> f64 + Sync,
+) -> f64 {
+    sum_chunks(pairs, min_len, |chunk| {
+        let mut sum = CompensatedSum::default();
+        for pair in chunk {
+            sum.add(distance(pair));
+        }
+        sum
+    })
+}
+
+// Sums every SUM_CHUNK_SIZE pairs with chunk_sum on any thread, then merges the chunks in order
+pub fn sum_chunks(
+    pairs: &[Pair],
+    min_len: usize,
+    chunk_sum: impl Fn(&[Pair]) -> CompensatedSum + Sync,
+) -> f64 {
+    profile_block!("sum_distances");
+
+    pairs
+        .par_chunks(SUM_CHUNK_SIZE)
+        .with_min_len(min_len)
+        .map(&chunk_sum)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .fold(CompensatedSum::default(), |mut sum, chunk| {
+            sum.merge(chunk);
+            sum
+        })
+        .total()
+}
+
+pub fn sum_haversines(pairs: &[Pair], radius: f64) -> f64 {
+    sum_distances(pairs, |p| haversine_of_degrees(p, radius))
+}
+
+// WGS-84 ellipsoid, semi-major axis in km and flattening
+const ELLIPSOID_A: f64 = 6378.137;
+const ELLIPSOID_F: f64 = 1.0 / 298.257_223_563;
+
+const VINCENTY_ITERATIONS: usize = 200;
+const VINCENTY_TOLERANCE: f64 = 1e-12;
+
+// Distance in km along the WGS-84 ellipsoid by Vincenty's inverse formula. Nearly antipodal
+// points can fail to converge, those fall back to the spherical haversine
+pub fn vincenty_of_degrees(p: &Pair) -> f64 {
+    profile_block!("vincenty");
+
+    let (a, f) = (ELLIPSOID_A, ELLIPSOID_F);
+    let b = (1.0 - f) * a;
+
+    let l = (p.x1 - p.x0).to_radians();
+    let (sin_u1, cos_u1) = ((1.0 - f) * p.y0.to_radians().tan()).atan().sin_cos();
+    let (sin_u2, cos_u2) = ((1.0 - f) * p.y1.to_radians().tan()).atan().sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..VINCENTY_ITERATIONS {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+
+        let sin_sigma = f64::sqrt(
+            (cos_u2 * sin_lambda).powi(2)
+                + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2),
+        );
+        if sin_sigma == 0.0 {
+            return 0.0;
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = f64::atan2(sin_sigma, cos_sigma);
+
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos2_alpha = 1.0 - sin_alpha * sin_alpha;
+        // Zero when both points are on the equator
+        let cos_2sigma_m = if cos2_alpha == 0.0 {
+            0.0
+        } else {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos2_alpha
+        };
+
+        let c = f / 16.0 * cos2_alpha * (4.0 + f * (4.0 - 3.0 * cos2_alpha));
+        let previous = lambda;
+        lambda = l
+            + (1.0 - c)
+                * f
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+
+        if (lambda - previous).abs() < VINCENTY_TOLERANCE {
+            let u2 = cos2_alpha * (a * a - b * b) / (b * b);
+            let big_a = 1.0 + u2 / 16384.0 * (4096.0 + u2 * (-768.0 + u2 * (320.0 - 175.0 * u2)));
+            let big_b = u2 / 1024.0 * (256.0 + u2 * (-128.0 + u2 * (74.0 - 47.0 * u2)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                            - big_b / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+
+            return b * big_a * (sigma - delta_sigma);
+        }
+    }
+
+    haversine_of_degrees(p, EARTH_RADIUS_KM)
+}
+
+const LOW_BITS: u64 = 0x0101_0101_0101_0101;
+const HIGH_BITS: u64 = 0x8080_8080_8080_8080;
+
+// Offset of the first byte at or after start. Checks 8 bytes at a time by xoring a word with the
+// byte repeated, which zeroes the matching bytes, then finding the lowest zero byte. Borrows can
+// only mark bytes above a real zero, so the lowest marked byte is always the first match
+fn find_byte(input: &[u8], start: usize, byte: u8) -> Option<usize> {
+    let pattern = LOW_BITS * byte as u64;
+
+    let mut words = input[start..].chunks_exact(8);
+    let mut offset = start;
+
+    for word in &mut words {
+        let x = u64::from_le_bytes(word.try_into().unwrap()) ^ pattern;
+        let zeros = x.wrapping_sub(LOW_BITS) & !x & HIGH_BITS;
+
+        if zeros != 0 {
+            return Some(offset + zeros.trailing_zeros() as usize / 8);
+        }
+        offset += 8;
+    }
+
+    words
+        .remainder()
+        .iter()
+        .position(|&b| b == byte)
+        .map(|i| offset + i)
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub offset: usize,
+    pub expected: &'static str,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Expected {} at byte {}", self.expected, self.offset)
+    }
+}
+
+fn skip_whitespace(input: &[u8], index: &mut usize) {
+    while *index < input.len() && input[*index].is_ascii_whitespace() {
+        *index += 1;
+    }
+}
+
+fn expect(input: &[u8], index: &mut usize, token: &'static str) -> Result<(), ParseError> {
+    skip_whitespace(input, index);
+
+    if input[*index..].starts_with(token.as_bytes()) {
+        *index += token.len();
+        Ok(())
+    } else {
+        Err(ParseError {
+            offset: *index,
+            expected: token,
+        })
+    }
+}
+
+// Parses `"key": value` up to the delimiter, leaving index on it
+fn parse_field(
+    input: &[u8],
+    index: &mut usize,
+    key: &'static str,
+    delimiter: &'static str,
+) -> Result<f64, ParseError> {
+    let missing = |expected| ParseError {
+        offset: input.len(),
+        expected,
+    };
+
+    let start = *index;
+    let colon = find_byte(input, start, b':').ok_or(missing(":"))?;
+
+    if input[start..colon].trim_ascii() != key.as_bytes() {
+        let mut offset = start;
+        skip_whitespace(input, &mut offset);
+        return Err(ParseError {
+            offset,
+            expected: key,
+        });
+    }
+
+    let end = find_byte(input, colon + 1, delimiter.as_bytes()[0]).ok_or(missing(delimiter))?;
+    *index = end;
+
+    profile_block!("parse_float");
+    fast_float::parse(input[colon + 1..end].trim_ascii()).map_err(|_| ParseError {
+        offset: colon + 1,
+        expected: "number",
+    })
+}
+
+// Parses the records in input[start..end], which must begin at a record and end after one,
+// handing each pair to f
+fn fold_records(
+    input: &[u8],
+    start: usize,
+    end: usize,
+    mut f: impl FnMut(Pair),
+) -> Result<(), ParseError> {
+    profile_block!("parse_records");
+
+    let mut index = start;
+    let mut records = 0;
+
+    while index < end {
+        expect(input, &mut index, "{")?;
+
+        let x0 = parse_field(input, &mut index, "\"x0\"", ",")?;
+        index += 1;
+        let y0 = parse_field(input, &mut index, "\"y0\"", ",")?;
+        index += 1;
+        let x1 = parse_field(input, &mut index, "\"x1\"", ",")?;
+        index += 1;
+        let y1 = parse_field(input, &mut index, "\"y1\"", "}")?;
+        index += 1;
+
+        f(Pair { x0, y0, x1, y1 });
+
+        records += 1;
+        if records == progress::BATCH {
+            progress::add(records);
+            records = 0;
+        }
+
+        skip_whitespace(input, &mut index);
+        if input.get(index) == Some(&b',') {
+            index += 1;
+            skip_whitespace(input, &mut index);
+        } else {
+            break;
+        }
+
+        // Chunks end after a comma with the next record at end, the array ends at its ]
+        if index == end && input.get(end) == Some(&b']') {
+            return Err(ParseError {
+                offset: index,
+                expected: "{",
+            });
+        }
+    }
+
+    progress::add(records);
+
+    if index != end {
+        return Err(ParseError {
+            offset: index,
+            expected: ",",
+        });
+    }
+
+    Ok(())
+}
+
+// Offsets splitting the records in input[start..end] into roughly equal chunks. Records have no
+// nested objects, so the next } after an offset is always the end of a record. Chunks are only
+// cut after the comma separating two records, a } without one is left inside the chunk so it
+// fails there exactly like it does when parsing on one thread
+fn split_records(input: &[u8], start: usize, end: usize, chunks: usize) -> Vec<usize> {
+    let mut splits = vec![start];
+    let chunk_size = ((end - start) / chunks).max(1);
+
+    for chunk in 1..chunks {
+        let mut index = (start + chunk * chunk_size).max(*splits.last().unwrap());
+
+        index = match find_byte(input, index, b'}') {
+            Some(curly) => curly + 1,
+            None => break,
+        };
+        skip_whitespace(input, &mut index);
+        if input.get(index) != Some(&b',') {
+            continue;
+        }
+        index += 1;
+        skip_whitespace(input, &mut index);
+
+        if index >= end {
+            break;
+        }
+        splits.push(index);
+    }
+
+    splits.push(end);
+    splits
+}
+
+// Parses the lines in input[start..end], each holding one record or only whitespace, handing each
+// pair to f
+fn fold_lines(
+    input: &[u8],
+    start: usize,
+    end: usize,
+    mut f: impl FnMut(Pair),
+) -> Result<(), ParseError> {
+    profile_block!("parse_lines");
+
+    let mut index = start;
+    let mut records = 0;
+
+    while index < end {
+        let line_end = find_byte(input, index, b'\n').map_or(end, |newline| newline.min(end));
+
+        if input[index..line_end].trim_ascii().is_empty() {
+            index = line_end + 1;
+            continue;
+        }
+
+        expect(input, &mut index, "{")?;
+
+        let x0 = parse_field(input, &mut index, "\"x0\"", ",")?;
+        index += 1;
+        let y0 = parse_field(input, &mut index, "\"y0\"", ",")?;
+        index += 1;
+        let x1 = parse_field(input, &mut index, "\"x1\"", ",")?;
+        index += 1;
+        let y1 = parse_field(input, &mut index, "\"y1\"", "}")?;
+        index += 1;
+
+        // The closing } has to be on the same line, with nothing after it but whitespace
+        if index > line_end || !input[index..line_end].trim_ascii().is_empty() {
+            skip_whitespace(input, &mut index);
+            return Err(ParseError {
+                offset: index,
+                expected: "\\n",
+            });
+        }
+
+        f(Pair { x0, y0, x1, y1 });
+
+        records += 1;
+        if records == progress::BATCH {
+            progress::add(records);
+            records = 0;
+        }
+
+        index = line_end + 1;
+    }
+
+    progress::add(records);
+
+    Ok(())
+}
+
+// Offsets splitting input[start..end] into roughly equal chunks of whole lines
+fn split_lines(input: &[u8], start: usize, end: usize, chunks: usize) -> Vec<usize> {
+    let mut splits = vec![start];
+    let chunk_size = ((end - start) / chunks).max(1);
+
+    for chunk in 1..chunks {
+        let index = (start + chunk * chunk_size).max(*splits.last().unwrap());
+        if index >= end {
+            break;
+        }
+
+        let index = match find_byte(input, index, b'\n') {
+            Some(newline) => newline + 1,
+            None => break,
+        };
+
+        if index >= end {
+            break;
+        }
+        splits.push(index);
+    }
+
+    splits.push(end);
+    splits
+}
+
+// Compact records from gen_input are around 90 bytes, guessing low overestimates the pair count
+// so parsing fits in one allocation
+const MIN_RECORD_BYTES_ESTIMATE: usize = 64;
+
+// Pairs the text parsers reserve room for in this many bytes of records
+pub fn estimated_pairs(bytes: usize) -> usize {
+    bytes / MIN_RECORD_BYTES_ESTIMATE
+}
+
+// Checks the object around the pairs array, returning the range of the records inside it
+fn record_range(input: &[u8]) -> Result<(usize, usize), ParseError> {
+    let mut index = 0;
+
+    expect(input, &mut index, "{")?;
+    expect(input, &mut index, "\"pairs\"")?;
+    expect(input, &mut index, ":")?;
+    expect(input, &mut index, "[")?;
+
+    skip_whitespace(input, &mut index);
+    let start = index;
+
+    // The records end at the ] closing the array, found by walking back from the end of input
+    let mut end = input.len();
+    for token in [b'}', b']'] {
+        while end > start && input[end - 1].is_ascii_whitespace() {
+            end -= 1;
+        }
+        if end == start || input[end - 1] != token {
+            return Err(ParseError {
+                offset: end,
+                expected: if token == b'}' { "}" } else { "]" },
+            });
+        }
+        end -= 1;
+    }
+
+    Ok((start, end))
+}
+
+// Json holds the pairs in an array, ndjson holds one pair object per line
+#[derive(Clone, Copy)]
+enum Layout {
+    Array,
+    Lines,
+}
+
+impl Layout {
+    fn record_range(self, input: &[u8]) -> Result<(usize, usize), ParseError> {
+        match self {
+            Layout::Array => record_range(input),
+            Layout::Lines => Ok((0, input.len())),
+        }
+    }
+
+    fn split(self, input: &[u8], start: usize, end: usize, chunks: usize) -> Vec<usize> {
+        match self {
+            Layout::Array => split_records(input, start, end, chunks),
+            Layout::Lines => split_lines(input, start, end, chunks),
+        }
+    }
+
+    fn fold(
+        self,
+        input: &[u8],
+        start: usize,
+        end: usize,
+        f: impl FnMut(Pair),
+    ) -> Result<(), ParseError> {
+        match self {
+            Layout::Array => fold_records(input, start, end, f),
+            Layout::Lines => fold_lines(input, start, end, f),
+        }
+    }
+}
+
+// Collecting straight into a Result keeps whichever chunk failed first in time. The chunks are
+// in input order, so the first failed one holds the error at the lowest offset every run
+fn first_error<T>(chunks: Vec<Result<T, ParseError>>) -> Result<Vec<T>, ParseError> {
+    chunks.into_iter().collect()
+}
+
+fn parse_layout(input: &[u8], chunks: usize, layout: Layout) -> Result<Pairs, ParseError> {
+    let (start, end) = layout.record_range(input)?;
+
+    let mut res = Pairs {
+        pairs: Vec::with_capacity(estimated_pairs(end - start)),
+    };
+
+    let splits = layout.split(input, start, end, chunks);
+    let chunks = splits
+        .par_windows(2)
+        .map(|split| {
+            let mut pairs = Vec::new();
+            layout.fold(input, split[0], split[1], |pair| pairs.push(pair))?;
+            Ok(pairs)
+        })
+        .collect::<Vec<_>>();
+    let chunks = first_error(chunks)?;
+
+    for pairs in chunks {
+        res.pairs.extend(pairs);
+    }
+
+    Ok(res)
+}
+
+fn fold_layout(
+    input: &[u8],
+    chunks: usize,
+    layout: Layout,
+    distance: impl Fn(&Pair) -> f64 + Sync,
+) -> Result<(f64, usize), ParseError> {
+    let (start, end) = layout.record_range(input)?;
+
+    let splits = layout.split(input, start, end, chunks);
+    let chunks = splits
+        .par_windows(2)
+        .map(|split| {
+            let mut sum = CompensatedSum::default();
+            let mut count = 0;
+            layout.fold(input, split[0], split[1], |pair| {
+                sum.add(distance(&pair));
+                count += 1;
+            })?;
+            Ok((sum, count))
+        })
+        .collect::<Vec<_>>();
+
+    first_error(chunks).map(|chunks| {
+        // Combined in input order so the result doesn't depend on which thread finished first
+        let (sum, count) = chunks.into_iter().fold(
+            (CompensatedSum::default(), 0),
+            |(mut sum, count), (chunk, chunk_count)| {
+                sum.merge(chunk);
+                (sum, count + chunk_count)
+            },
+        );
+        (sum.total(), count)
+    })
+}
+
+// Parses on chunks threads when more than one, each taking a run of whole records
+pub fn parse(input: &[u8], chunks: usize) -> Result<Pairs, ParseError> {
+    profile_block!("parse");
+    parse_layout(input, chunks, Layout::Array)
+}
+
+// Sums the haversines while parsing without keeping the pairs, returning the sum and pair count
+pub fn parse_and_fold(
+    input: &[u8],
+    chunks: usize,
+    distance: impl Fn(&Pair) -> f64 + Sync,
+) -> Result<(f64, usize), ParseError> {
+    profile_block!("parse_and_fold");
+    fold_layout(input, chunks, Layout::Array, distance)
+}
+
+// Like parse for one pair object per line, chunks split at line ends
+pub fn parse_ndjson(input: &[u8], chunks: usize) -> Result<Pairs, ParseError> {
+    profile_block!("parse_ndjson");
+    parse_layout(input, chunks, Layout::Lines)
+}
+
+// Like parse_and_fold for one pair object per line. Each line is only read once, so a memory
+// mapped input never needs more than the pages being parsed resident
+pub fn parse_ndjson_and_fold(
+    input: &[u8],
+    chunks: usize,
+    distance: impl Fn(&Pair) -> f64 + Sync,
+) -> Result<(f64, usize), ParseError> {
+    profile_block!("parse_ndjson_and_fold");
+    fold_layout(input, chunks, Layout::Lines, distance)
+}
+
+pub const BINARY_RECORD_SIZE: usize = 4 * std::mem::size_of::<f64>();
+
+pub fn parse_binary(input: &[u8]) -> Result<Pairs, ParseError> {
+    profile_block!("parse_binary");
+
+    let remainder = input.len() % BINARY_RECORD_SIZE;
+    if remainder != 0 {
+        return Err(ParseError {
+            offset: input.len() - remainder,
+            expected: "a whole record",
+        });
+    }
+
+    let read = |bytes: &[u8]| f64::from_le_bytes(bytes.try_into().unwrap());
+
+    Ok(Pairs {
+        pairs: input
+            .chunks_exact(BINARY_RECORD_SIZE)
+            .map(|record| Pair {
+                x0: read(&record[0..8]),
+                y0: read(&record[8..16]),
+                x1: read(&record[16..24]),
+                y1: read(&record[24..32]),
+            })
+            .collect(),
+    })
+}
+
+// Parses a field up to the next delimiter, leaving index on it. The last field of the last line
+// may end at the end of input instead of a newline
+fn parse_csv_field(input: &[u8], index: &mut usize, delimiter: u8) -> Result<f64, ParseError> {
+    let start = *index;
+    *index = match find_byte(input, start, delimiter) {
+        Some(end) => end,
+        None if delimiter == b'\n' => input.len(),
+        None => {
+            return Err(ParseError {
+                offset: input.len(),
+                expected: ",",
+            })
+        }
+    };
+
+    // trim_ascii also takes the \r off \r\n line endings
+    fast_float::parse(input[start..*index].trim_ascii()).map_err(|_| ParseError {
+        offset: start,
+        expected: "number",
+    })
+}
+
+pub fn parse_csv(input: &[u8]) -> Result<Pairs, ParseError> {
+    profile_block!("parse_csv");
+
+    let mut res = Pairs {
+        pairs: Vec::with_capacity(estimated_pairs(input.len())),
+    };
+
+    let mut index = 0;
+
+    // A header is any first line that doesn't start with a number
+    let first = input.iter().find(|b| !b.is_ascii_whitespace());
+    if first.is_some_and(|b| !matches!(b, b'0'..=b'9' | b'-' | b'+' | b'.')) {
+        index = find_byte(input, 0, b'\n').unwrap_or(input.len());
+    }
+
+    while index < input.len() {
+        if input[index].is_ascii_whitespace() {
+            index += 1;
+            continue;
+        }
+
+        let x0 = parse_csv_field(input, &mut index, b',')?;
+        index += 1;
+        let y0 = parse_csv_field(input, &mut index, b',')?;
+        index += 1;
+        let x1 = parse_csv_field(input, &mut index, b',')?;
+        index += 1;
+        let y1 = parse_csv_field(input, &mut index, b'\n')?;
+        index += 1;
+
+        res.pairs.push(Pair { x0, y0, x1, y1 });
+    }
+
+    Ok(res)
+}
+
+fn parse_reference(input: &[u8]) -> Result<Pairs, serde_json::Error> {
+    serde_json::from_slice::<Pairs>(input)
+}
+
+const VALIDATE_EPSILON: f64 = 1e-6;
+
+fn pairs_match(a: &Pair, b: &Pair) -> bool {
+    (a.x0 - b.x0).abs() <= VALIDATE_EPSILON
+        && (a.y0 - b.y0).abs() <= VALIDATE_EPSILON
+        && (a.x1 - b.x1).abs() <= VALIDATE_EPSILON
+        && (a.y1 - b.y1).abs() <= VALIDATE_EPSILON
+}
+
+// Compares the fast parser against serde_json, reporting the first pair that differs
+pub fn validate(input: &[u8], parsed: &Pairs) -> Result<(), String> {
+    let reference = parse_reference(input).map_err(|e| format!("Reference parser failed: {e}"))?;
+
+    if let Some(index) = parsed
+        .pairs
+        .iter()
+        .zip(&reference.pairs)
+        .position(|(a, b)| !pairs_match(a, b))
+    {
+        return Err(format!(
+            "Pair {index} differs: parsed {:?}, reference {:?}",
+            parsed.pairs[index], reference.pairs[index]
+        ));
+    }
+
+    if parsed.pairs.len() != reference.pairs.len() {
+        return Err(format!(
+            "Parsed {} pairs, reference has {}, first missing pair is {}",
+            parsed.pairs.len(),
+            reference.pairs.len(),
+            parsed.pairs.len().min(reference.pairs.len())
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    const RECORD: &str = r#"{"x0":1.5,"y0":-2.25,"x1":3,"y1":4}"#;
+
+    // {"pairs":[...]} with the records joined by separator
+    fn json(records: usize, separator: &str) -> Vec<u8> {
+        format!(r#"{{"pairs":[{}]}}"#, vec![RECORD; records].join(separator)).into_bytes()
+    }
+
+    fn error_offset(result: Result<Pairs, ParseError>) -> Option<usize> {
+        result.err().map(|e| e.offset)
+    }
+
+    // The same pairs for a seed every run, spread over the whole globe
+    pub(crate) fn random_pairs(count: usize, seed: u64) -> Vec<Pair> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| Pair {
+                x0: rng.gen_range(-180.0..180.0),
+                y0: rng.gen_range(-90.0..90.0),
+                x1: rng.gen_range(-180.0..180.0),
+                y1: rng.gen_range(-90.0..90.0),
+            })
+            .collect()
+    }
+
+    // haversine_of_degrees on the pair rounded to f32, computed in f32
+    fn haversine_f32(p: &Pair) -> f32 {
+        let (x0, y0, x1, y1) = (p.x0 as f32, p.y0 as f32, p.x1 as f32, p.y1 as f32);
+
+        let sin_dy = ((y1 - y0).to_radians() / 2.0).sin();
+        let sin_dx = ((x1 - x0).to_radians() / 2.0).sin();
+        let root_term =
+            sin_dy * sin_dy + y0.to_radians().cos() * y1.to_radians().cos() * sin_dx * sin_dx;
+
+        2.0 * EARTH_RADIUS_KM as f32 * root_term.sqrt().asin()
+    }
+
+    #[test]
+    fn f32_drifts_from_f64() {
+        const PAIRS: usize = 1_000_000;
+        let pairs = random_pairs(PAIRS, 32);
+
+        let f64_average = sum_haversines(&pairs, EARTH_RADIUS_KM) / PAIRS as f64;
+        let f32_distances = pairs
+            .iter()
+            .map(haversine_f32)
+            .map(|d| d as f64)
+            .sum::<f64>()
+            / PAIRS as f64;
+        let f32_sum = pairs.iter().map(haversine_f32).sum::<f32>() / PAIRS as f32;
+
+        // Around 1e-5 km off from rounding the math to f32, and 0.2 km from also summing in f32
+        let distances_error = (f32_distances - f64_average).abs();
+        let sum_error = (f32_sum as f64 - f64_average).abs();
+        assert!(distances_error < 1e-3, "{distances_error}");
+        assert!(sum_error > 100.0 * distances_error, "{sum_error}");
+        assert!(sum_error < 1.0, "{sum_error}");
+    }
+
+    fn pair(x0: f64, y0: f64, x1: f64, y1: f64) -> Pair {
+        Pair { x0, y0, x1, y1 }
+    }
+
+    fn assert_close(a: f64, b: f64, epsilon: f64) {
+        assert!((a - b).abs() <= epsilon, "{a} != {b}");
+    }
+
+    #[test]
+    fn haversine_matches_known_arcs() {
+        use std::f64::consts::PI;
+        let r = EARTH_RADIUS_KM;
+
+        // A degree along the equator is pi / 180 radii, not twice that
+        assert_close(
+            haversine_of_degrees(&pair(0.0, 0.0, 1.0, 0.0), r),
+            r * PI / 180.0,
+            1e-9,
+        );
+        assert_close(
+            haversine_of_degrees(&pair(0.0, 0.0, 0.0, 90.0), r),
+            r * PI / 2.0,
+            1e-9,
+        );
+        assert_close(
+            haversine_of_degrees(&pair(-90.0, 0.0, 90.0, 0.0), r),
+            r * PI,
+            1e-9,
+        );
+        assert_close(
+            haversine_of_degrees(&pair(10.0, 20.0, 10.0, 20.0), r),
+            0.0,
+            1e-9,
+        );
+
+        // Any meridian from pole to pole
+        assert_close(
+            haversine_of_degrees(&pair(37.0, -90.0, 37.0, 90.0), r),
+            r * PI,
+            1e-9,
+        );
+    }
+
+    // The law of cosines loses precision for close points, where acos is flat
+    #[test]
+    fn haversine_matches_the_spherical_law_of_cosines() {
+        for p in random_pairs(1000, 46) {
+            let (y0, y1) = (p.y0.to_radians(), p.y1.to_radians());
+            let cos = y0.sin() * y1.sin() + y0.cos() * y1.cos() * (p.x1 - p.x0).to_radians().cos();
+            let reference = EARTH_RADIUS_KM * cos.clamp(-1.0, 1.0).acos();
+
+            assert_close(haversine_of_degrees(&p, EARTH_RADIUS_KM), reference, 1e-3);
+        }
+    }
+
+    fn degrees(degrees: f64, minutes: f64, seconds: f64) -> f64 {
+        degrees.signum() * (degrees.abs() + minutes / 60.0 + seconds / 3600.0)
+    }
+
+    #[test]
+    fn vincenty_matches_published_distances() {
+        // Flinders Peak to Buninyong, the example in Vincenty's paper
+        let flinders_peak = (degrees(144.0, 25.0, 29.5244), degrees(-37.0, 57.0, 3.7203));
+        let buninyong = (degrees(143.0, 55.0, 35.3839), degrees(-37.0, 39.0, 10.1561));
+        let p = pair(flinders_peak.0, flinders_peak.1, buninyong.0, buninyong.1);
+        assert_close(vincenty_of_degrees(&p), 54.972271, 1e-6);
+
+        // A degree of the equator, and the equator to the north pole along a meridian
+        assert_close(
+            vincenty_of_degrees(&pair(0.0, 0.0, 1.0, 0.0)),
+            ELLIPSOID_A * std::f64::consts::PI / 180.0,
+            1e-9,
+        );
+        assert_close(
+            vincenty_of_degrees(&pair(0.0, 0.0, 0.0, 90.0)),
+            10001.965729,
+            1e-6,
+        );
+    }
+
+    #[test]
+    fn vincenty_is_near_the_haversine() {
+        // The ellipsoid is within about half a percent of the sphere
+        for p in random_pairs(1000, 76) {
+            let vincenty = vincenty_of_degrees(&p);
+            let haversine = haversine_of_degrees(&p, EARTH_RADIUS_KM);
+            assert_close(vincenty, haversine, haversine * 0.006);
+        }
+    }
+
+    // Exact sum of the distances in fixed point, rounded to f64 once at the end
+    fn exact_sum(distances: &[f64]) -> f64 {
+        const SCALE: f64 = (1u128 << 80) as f64;
+        distances.iter().map(|&d| (d * SCALE) as i128).sum::<i128>() as f64 / SCALE
+    }
+
+    #[test]
+    fn compensated_sum_is_closer_than_a_naive_f32_sum() {
+        let pairs = random_pairs(1_000_000, 88);
+        let distances = pairs
+            .iter()
+            .map(|p| haversine_of_degrees(p, EARTH_RADIUS_KM))
+            .collect::<Vec<_>>();
+        let exact = exact_sum(&distances);
+
+        let compensated = sum_haversines(&pairs, EARTH_RADIUS_KM);
+        let naive = distances.iter().map(|&d| d as f32).sum::<f32>() as f64;
+
+        assert!(
+            (compensated - exact).abs() <= exact * f64::EPSILON,
+            "{compensated} != {exact}"
+        );
+        assert!((naive - exact).abs() > 1e6 * (compensated - exact).abs());
+    }
+
+    #[test]
+    fn sum_is_the_same_on_any_number_of_threads() {
+        let pairs = random_pairs(100_000, 88);
+        let sum = |threads, min_len| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    sum_distances_with_min_len(&pairs, min_len, |p| {
+                        haversine_of_degrees(p, EARTH_RADIUS_KM)
+                    })
+                })
+        };
+
+        let one = sum(1, 1);
+        for (threads, min_len) in [(2, 1), (3, 1), (8, 1), (8, 2), (8, 5)] {
+            assert_eq!(sum(threads, min_len).to_bits(), one.to_bits());
+        }
+    }
+
+    #[test]
+    fn validate_reports_what_differs() {
+        let input = json(3, ",");
+        let parsed = parse(&input, 1).unwrap();
+        assert!(validate(&input, &parsed).is_ok());
+
+        let mut wrong = parse(&input, 1).unwrap();
+        wrong.pairs[1].y1 += 1.0;
+        assert!(validate(&input, &wrong)
+            .unwrap_err()
+            .starts_with("Pair 1 differs"));
+
+        let mut short = parse(&input, 1).unwrap();
+        short.pairs.pop();
+        assert_eq!(
+            validate(&input, &short).unwrap_err(),
+            "Parsed 2 pairs, reference has 3, first missing pair is 2"
+        );
+
+        let mut malformed = input.clone();
+        malformed.truncate(input.len() - 2);
+        assert!(validate(&malformed, &parsed)
+            .unwrap_err()
+            .starts_with("Reference parser failed"));
+    }
+
+    #[test]
+    fn every_pair_is_parsed() {
+        let spaced = br#"{ "pairs": [
+            {"x0": 1, "y0": 2, "x1": 3, "y1": 4},
+            {"x0": 5, "y0": 6, "x1": 7, "y1": 8},
+            {"x0": 9, "y0": 10, "x1": 11, "y1": 12}
+        ] }
+        "#;
+        let compact = spaced
+            .iter()
+            .copied()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect::<Vec<_>>();
+
+        for input in [&spaced[..], &compact[..]] {
+            let pairs = parse(input, 1).unwrap().pairs;
+            assert_eq!(pairs.len(), 3);
+
+            let last = &pairs[2];
+            assert_eq!(
+                (last.x0, last.y0, last.x1, last.y1),
+                (9.0, 10.0, 11.0, 12.0)
+            );
+
+            assert_eq!(parse_and_fold(input, 1, |_| 1.0).unwrap(), (3.0, 3));
+        }
+    }
+
+    #[test]
+    fn chunks_agree_with_one_thread() {
+        for input in [json(40, ","), json(40, " ,\n  ")] {
+            let serial = parse(&input, 1).unwrap();
+            for chunks in 2..=16 {
+                assert_eq!(
+                    parse(&input, chunks).unwrap().pairs.len(),
+                    serial.pairs.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn answer_sits_next_to_the_data() {
+        assert_eq!(answer_path("input.json"), "input.json.answer");
+        assert_eq!(answer_path("input.f64"), "input.f64.answer");
+        assert_eq!(answer_path("data/big.json.gz"), "data/big.json.answer");
+    }
+
+    #[test]
+    fn empty_array_has_no_pairs() {
+        assert_eq!(parse(br#"{"pairs":[]}"#, 1).unwrap().pairs.len(), 0);
+        assert_eq!(parse(b"{ \"pairs\" : [ ] }", 4).unwrap().pairs.len(), 0);
+    }
+
+    #[test]
+    fn trailing_comma_is_rejected() {
+        for separator in [",", ", ", ",\n"] {
+            let mut input = json(40, ",");
+            let end = input.len() - 2;
+            input.splice(end..end, separator.bytes());
+
+            for chunks in 1..=16 {
+                let e = parse(&input, chunks).unwrap_err();
+                assert_eq!(e.expected, "{", "{chunks} chunks");
+                assert_eq!(e.offset, input.len() - 2, "{chunks} chunks");
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_report_the_first_error() {
+        // Bad numbers early and late, which land in different chunks
+        let mut input = String::from_utf8(json(40, ",")).unwrap();
+        let second = input.find("1.5").unwrap() + RECORD.len() + 1;
+        let last = input.rfind("1.5").unwrap();
+        input.replace_range(last..last + 3, "1.x");
+        input.replace_range(second..second + 3, "1.x");
+        let input = input.into_bytes();
+
+        let serial = error_offset(parse(&input, 1));
+        assert!(serial.is_some());
+        for _ in 0..20 {
+            assert_eq!(error_offset(parse(&input, 16)), serial);
+            assert_eq!(
+                parse_and_fold(&input, 16, |_| 1.0).err().map(|e| e.offset),
+                serial
+            );
+        }
+    }
+
+    #[test]
+    fn chunks_reject_a_missing_comma() {
+        // Every separator but one is a comma, as in [{..},{..} {..},{..}]
+        let input = format!(
+            r#"{{"pairs":[{},{} {},{}]}}"#,
+            vec![RECORD; 20].join(","),
+            RECORD,
+            RECORD,
+            vec![RECORD; 20].join(",")
+        )
+        .into_bytes();
+
+        let serial = error_offset(parse(&input, 1));
+        assert!(serial.is_some());
+        for chunks in 2..=16 {
+            assert_eq!(
+                error_offset(parse(&input, chunks)),
+                serial,
+                "{chunks} chunks"
+            );
+        }
+    }
+}
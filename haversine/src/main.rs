@@ -1,172 +1,477 @@
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
-use serde::{Deserialize, Serialize};
-use std::{
-    arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8},
-    fs, str,
-    time::Instant,
+use clap::{Parser, ValueEnum};
+use pap::{
+    answer_path, estimated_pairs, gzip, haversine_of_degrees, mmap, mmap::Mmap, parse,
+    parse_and_fold, parse_binary, parse_csv, parse_ndjson, parse_ndjson_and_fold, progress,
+    repetition::RepetitionTester, sum_distances_with_min_len, timer, validate, vincenty_of_degrees,
+    Pair, Pairs, ParseError, EARTH_RADIUS_KM,
 };
+use serde::Serialize;
+use std::{fs, hint::black_box, io, mem, ops::Deref, process, time::Duration};
 
-#[derive(Default, Debug, Serialize, Deserialize)]
-pub struct Pair {
-    pub x0: f32,
-    pub y0: f32,
-    pub x1: f32,
-    pub y1: f32,
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    Json,
+    // Flat little endian f64 [x0, y0, x1, y1] records
+    Binary,
+    // x0,y0,x1,y1 lines with an optional header
+    Csv,
+    // One json pair object per line
+    Ndjson,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize)]
-pub struct Pairs {
-    pub pairs: Vec<Pair>,
+impl InputFormat {
+    fn default_path(self) -> &'static str {
+        match self {
+            InputFormat::Json => "input.json",
+            InputFormat::Binary => "input.f64",
+            InputFormat::Csv => "input.csv",
+            InputFormat::Ndjson => "input.ndjson",
+        }
+    }
+
+    // gen_input writes the default path of every format but csv
+    fn generate_command(self) -> Option<&'static str> {
+        match self {
+            InputFormat::Json => Some("cargo run --release --bin gen_input"),
+            InputFormat::Binary => Some("cargo run --release --bin gen_input -- --format binary"),
+            InputFormat::Csv => None,
+            InputFormat::Ndjson => Some("cargo run --release --bin gen_input -- --ndjson"),
+        }
+    }
 }
 
-const EARTH_RADIUS_KM: f32 = 6371.0;
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Input file, defaults to input.json, input.f64, input.csv or input.ndjson depending on the
+    /// format
+    file: Option<String>,
+
+    /// Format of the input
+    #[arg(long, value_enum, default_value_t = InputFormat::Json)]
+    format: InputFormat,
+
+    /// Memory map the input instead of reading it into memory
+    #[arg(long)]
+    mmap: bool,
+
+    /// Parse json or ndjson on a single thread, for comparing against the parallel parser
+    #[arg(long)]
+    serial: bool,
+
+    /// Check the fast parser against serde_json before computing the average
+    #[arg(long)]
+    validate: bool,
+
+    /// Unit of the distances, with the matching radius of the Earth
+    #[arg(long, value_enum, default_value_t = Unit::Km)]
+    unit: Unit,
+
+    /// Radius of the sphere in the chosen unit, instead of the Earth's
+    #[arg(long)]
+    radius: Option<f64>,
+
+    /// Parse into a list of pairs before computing, json and ndjson are otherwise summed while
+    /// parsing
+    #[arg(long)]
+    collect: bool,
 
-fn haversine_of_degrees(p: &Pair) -> f32 {
-    let dy = (p.y1 - p.y0).to_radians();
-    let dx = (p.x1 - p.x0).to_radians();
-    let y0 = p.y0.to_radians();
-    let y1 = p.y1.to_radians();
+    /// Compute the haversines 8 at a time with AVX in f32, the usual way on CPUs without AVX
+    #[arg(long)]
+    simd: bool,
 
-    let sin_dy = f32::sin(dy / 2.0);
-    let sin_dx = f32::sin(dx / 2.0);
+    /// Formula for the distance between the points of a pair
+    #[arg(long, value_enum, default_value_t = Formula::Haversine)]
+    formula: Formula,
 
-    let root_term = (sin_dy * sin_dy) + f32::cos(y0) * f32::cos(y1) * (sin_dx * sin_dx);
-    2.0 * EARTH_RADIUS_KM * 2.0 * f32::asin(f32::sqrt(root_term))
+    /// Print how many records have been parsed every second
+    #[arg(long)]
+    progress: bool,
+
+    /// Repeat the parse and the math until neither gets faster for this many seconds
+    #[arg(long)]
+    repeat: Option<u64>,
+
+    /// Threads to parse and compute on, defaults to one per core
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    threads: Option<u64>,
+
+    /// How to print the result. json and csv hold the average, the count and the parse and math
+    /// seconds, in that order for csv. The math seconds are null or empty when the math ran
+    /// while parsing
+    #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+    output_format: OutputFormat,
+
+    /// Chunks of pairs each task sums at least when the pairs are collected first
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    min_len: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    // Labelled lines with the timings, reference and memory use
+    Plain,
+    // One object per run
+    Json,
+    // One row per run without a header, for appending runs to a file
+    Csv,
 }
 
-fn next_colon(input: &[u8], index: &mut usize) {
-    unsafe {
-        let colon = _mm_set1_epi8(b':' as i8);
+#[derive(Serialize)]
+struct Summary {
+    average: f64,
+    count: usize,
+    parse_seconds: f64,
+    math_seconds: Option<f64>,
+}
 
-        loop {
-            let chunk = _mm_loadu_si128(input.as_ptr().add(*index) as *const __m128i);
-            let eq = _mm_cmpeq_epi8(chunk, colon);
-            let mask = _mm_movemask_epi8(eq);
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Formula {
+    // Great circle distance on a sphere
+    Haversine,
+    // Distance on the WGS-84 ellipsoid, scaled from km to the unit like the reference
+    Vincenty,
+}
 
-            if mask != 0 {
-                *index += mask.trailing_zeros() as usize;
-                return;
-            }
-            *index += 16;
+#[derive(Copy, Clone, ValueEnum)]
+enum Unit {
+    Km,
+    Mi,
+    Nm,
+}
+
+impl Unit {
+    fn earth_radius(self) -> f64 {
+        match self {
+            Unit::Km => EARTH_RADIUS_KM,
+            Unit::Mi => 3958.8,
+            Unit::Nm => 3440.1,
         }
     }
 }
 
-fn next_comma(input: &[u8], index: &mut usize) {
-    unsafe {
-        let colon = _mm_set1_epi8(b',' as i8);
+fn parse_chunks(args: &Args) -> usize {
+    if args.serial {
+        1
+    } else {
+        rayon::current_num_threads() * 4
+    }
+}
 
-        loop {
-            let chunk = _mm_loadu_si128(input.as_ptr().add(*index) as *const __m128i);
-            let eq = _mm_cmpeq_epi8(chunk, colon);
-            let mask = _mm_movemask_epi8(eq);
+fn parse_input(args: &Args, input: &[u8]) -> Result<Pairs, ParseError> {
+    match args.format {
+        InputFormat::Json => parse(input, parse_chunks(args)),
+        InputFormat::Binary => parse_binary(input),
+        InputFormat::Csv => parse_csv(input),
+        InputFormat::Ndjson => parse_ndjson(input, parse_chunks(args)),
+    }
+}
 
-            if mask != 0 {
-                *index += mask.trailing_zeros() as usize;
-                return;
-            }
-            *index += 16;
-        }
+fn radius(args: &Args) -> f64 {
+    args.radius.unwrap_or(args.unit.earth_radius())
+}
+
+fn distance(args: &Args) -> impl Fn(&Pair) -> f64 + Sync {
+    let radius = radius(args);
+    let formula = args.formula;
+
+    move |p| match formula {
+        Formula::Haversine => haversine_of_degrees(p, radius),
+        Formula::Vincenty => vincenty_of_degrees(p) * radius / EARTH_RADIUS_KM,
+    }
+}
+
+fn sum_haversines(args: &Args, pairs: &Pairs) -> f64 {
+    #[cfg(target_arch = "x86_64")]
+    if args.simd {
+        return pap::simd::sum_haversines(&pairs.pairs, radius(args));
     }
+
+    sum_distances_with_min_len(&pairs.pairs, args.min_len as usize, distance(args))
 }
 
-fn next_end_curly(input: &[u8], index: &mut usize) {
-    unsafe {
-        let colon = _mm_set1_epi8(b'}' as i8);
+fn repeat(args: &Args, input: &[u8], pairs: &Pairs, seconds: u64) {
+    let try_for = Duration::from_secs(seconds);
 
-        loop {
-            let chunk = _mm_loadu_si128(input.as_ptr().add(*index) as *const __m128i);
-            let eq = _mm_cmpeq_epi8(chunk, colon);
-            let mask = _mm_movemask_epi8(eq);
+    let mut tester = RepetitionTester::new("Parse", input.len() as u64, try_for);
+    while tester.is_testing() {
+        tester.begin_time();
+        let parsed = parse_input(args, input);
+        tester.end_time();
+        drop(parsed);
+    }
+    tester.print();
+
+    let bytes = mem::size_of_val(pairs.pairs.as_slice()) as u64;
+    let mut tester = RepetitionTester::new("Math", bytes, try_for);
+    while tester.is_testing() {
+        tester.begin_time();
+        let sum = sum_haversines(args, pairs);
+        tester.end_time();
+        black_box(sum);
+    }
+    tester.print();
+}
+
+// Prints the result and timings, mid_time splits the input from the math when they were separate
+fn report(
+    args: &Args,
+    path: &str,
+    average: f64,
+    count: usize,
+    bytes: usize,
+    compressed_bytes: Option<usize>,
+    (start_time, mid_time, end_time): (u64, Option<u64>, u64),
+) {
+    if args.output_format != OutputFormat::Plain {
+        let cpu_frequency = timer::estimate_cpu_frequency();
+        let seconds = |cycles: u64| cycles as f64 / cpu_frequency as f64;
+
+        let summary = Summary {
+            average,
+            count,
+            parse_seconds: seconds(mid_time.unwrap_or(end_time) - start_time),
+            math_seconds: mid_time.map(|mid_time| seconds(end_time - mid_time)),
+        };
+
+        match args.output_format {
+            OutputFormat::Json => println!("{}", serde_json::to_string(&summary).unwrap()),
+            _ => println!(
+                "{},{},{},{}",
+                summary.average,
+                summary.count,
+                summary.parse_seconds,
+                summary
+                    .math_seconds
+                    .map_or(String::new(), |seconds| seconds.to_string())
+            ),
+        }
+        return;
+    }
+
+    println!("Result: {average}");
 
-            if mask != 0 {
-                *index += mask.trailing_zeros() as usize;
-                return;
+    // gen_input writes the expected average in km next to the data, distances scale with the radius
+    let answer_path = answer_path(path);
+    if let Ok(answer) = fs::read_to_string(&answer_path) {
+        match answer.trim().parse::<f64>() {
+            Ok(answer) => {
+                let answer = answer * radius(args) / EARTH_RADIUS_KM;
+                println!("Reference: {answer}");
+                println!("Difference: {}", (average - answer).abs());
             }
-            *index += 16;
+            Err(e) => eprintln!("Unable to parse {answer_path}: {e}"),
         }
     }
+    let cpu_frequency = timer::estimate_cpu_frequency();
+    let print_time = |label: &str, cycles: u64| {
+        println!(
+            "{label} = {} seconds ({cycles} cycles)",
+            cycles as f64 / cpu_frequency as f64
+        );
+    };
+
+    let print_throughput = |label: &str, cycles: u64| {
+        let seconds = cycles as f64 / cpu_frequency as f64;
+        println!(
+            "{label} = {} GB/s",
+            bytes as f64 / seconds / (1024.0 * 1024.0 * 1024.0)
+        );
+    };
+
+    match mid_time {
+        Some(mid_time) => {
+            print_time("Input", mid_time - start_time);
+            print_time("Math", end_time - mid_time);
+            print_time("Total", end_time - start_time);
+            print_throughput("Parse", mid_time - start_time);
+        }
+        None => {
+            print_time("Input and math", end_time - start_time);
+            print_time("Total", end_time - start_time);
+            print_throughput("Parse and math", end_time - start_time);
+        }
+    }
+    println!("Bytes = {bytes}");
+    // Binary input has an exact size, the text formats guess how many pairs to make room for
+    if args.format != InputFormat::Binary {
+        println!(
+            "Estimated pairs = {} ({count} parsed)",
+            estimated_pairs(bytes)
+        );
+    }
+    if let Some(compressed_bytes) = compressed_bytes {
+        println!("Compressed bytes = {compressed_bytes}");
+    }
+    println!(
+        "Throughput = {} haversines/second",
+        count as f64 / ((end_time - start_time) as f64 / cpu_frequency as f64)
+    );
+    println!("Threads = {}", rayon::current_num_threads());
+    println!("CPU timer frequency = {cpu_frequency} Hz");
+
+    #[cfg(feature = "profiler")]
+    pap::profiler::print(end_time - start_time, cpu_frequency);
+
+    if let Some(peak_rss_kb) = mmap::peak_rss_kb() {
+        println!("Peak RSS = {} MB", peak_rss_kb as f32 / 1024.0);
+    }
 }
 
-fn parse(input: &str) -> Pairs {
-    let mut res = Pairs { pairs: Vec::new() };
-    res.pairs.reserve(10_000_000);
+fn main() {
+    let args = Args::parse();
 
-    let input = input
-        .trim_start_matches("{\"pairs\":[")
-        .trim_end_matches("]}")
-        .as_bytes();
+    // Everything runs in the pool so the parse chunks follow the thread count too
+    match args.threads {
+        Some(threads) => {
+            let pool = match rayon::ThreadPoolBuilder::new()
+                .num_threads(threads as usize)
+                .build()
+            {
+                Ok(pool) => pool,
+                Err(e) => {
+                    eprintln!("Unable to start {threads} threads: {e}");
+                    process::exit(1);
+                }
+            };
+            pool.install(|| run(&args));
+        }
+        None => run(&args),
+    }
+}
 
-    let mut index = 0;
+// An average of no distances is undefined, so an empty input is an error rather than NaN
+fn no_pairs(path: &str) -> ! {
+    eprintln!("No pairs in {path}");
+    process::exit(1);
+}
 
-    while index + 16 < input.len() {
-        next_colon(input, &mut index);
-        let colon = index;
-        next_comma(input, &mut index);
-        let comma = index;
-        let part = &input[colon + 1..comma];
-        let x0 = fast_float::parse(part).unwrap();
+fn run(args: &Args) {
+    if args.validate && args.format != InputFormat::Json {
+        eprintln!("--validate is only supported for json input");
+        process::exit(1);
+    }
 
-        next_colon(input, &mut index);
-        let colon = index;
-        next_comma(input, &mut index);
-        let comma = index;
-        let part = &input[colon + 1..comma];
-        let y0 = fast_float::parse(part).unwrap();
+    if args.simd && args.formula != Formula::Haversine {
+        eprintln!("--simd only computes haversines");
+        process::exit(1);
+    }
 
-        next_colon(input, &mut index);
-        let colon = index;
-        next_comma(input, &mut index);
-        let comma = index;
-        let part = &input[colon + 1..comma];
-        let x1 = fast_float::parse(part).unwrap();
+    let path = args.file.as_deref().unwrap_or(args.format.default_path());
+    let read: io::Result<Box<dyn Deref<Target = [u8]>>> = if args.mmap {
+        Mmap::open(path).map(|m| Box::new(m) as _)
+    } else {
+        fs::read(path).map(|v| Box::new(v) as _)
+    };
+    let mut input = match read {
+        Ok(input) => input,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("Unable to find {path}");
+            if let Some(generate) = args.format.generate_command() {
+                eprintln!("Generate one with: {generate}");
+            }
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Unable to read {path}: {e}");
+            process::exit(1);
+        }
+    };
 
-        next_colon(input, &mut index);
-        let colon = index;
-        next_end_curly(input, &mut index);
-        let comma = index;
-        let part = &input[colon + 1..comma];
-        let y1 = fast_float::parse(part).unwrap();
+    // Gzipped input is inflated up front, the parsers split the whole input between threads
+    let compressed_bytes = gzip::is_gzip(&input).then_some(input.len());
+    if compressed_bytes.is_some() {
+        input = match gzip::decompress(&input) {
+            Ok(decompressed) => Box::new(decompressed),
+            Err(e) => {
+                eprintln!("Unable to decompress {path}: {e}");
+                process::exit(1);
+            }
+        };
+    }
+
+    let reporter = args.progress.then(|| progress::Reporter::start("Parsed"));
+
+    // Json and ndjson are summed while parsing unless something needs the pairs afterwards
+    let collect = args.collect || args.validate || args.simd || args.repeat.is_some();
+    let folds = matches!(args.format, InputFormat::Json | InputFormat::Ndjson);
+    if folds && !collect {
+        let start_time = timer::read_cpu_timer();
+        let chunks = parse_chunks(args);
+        let folded = match args.format {
+            InputFormat::Ndjson => parse_ndjson_and_fold(&input, chunks, distance(args)),
+            _ => parse_and_fold(&input, chunks, distance(args)),
+        };
+        let (sum, count) = match folded {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Unable to parse {path}: {e}");
+                process::exit(1);
+            }
+        };
+        let end_time = timer::read_cpu_timer();
+        drop(reporter);
 
-        res.pairs.push(Pair { x0, y0, x1, y1 });
+        if count == 0 {
+            no_pairs(path);
+        }
+        let average = sum / count as f64;
+        report(
+            args,
+            path,
+            average,
+            count,
+            input.len(),
+            compressed_bytes,
+            (start_time, None, end_time),
+        );
+        return;
     }
 
-    res
-}
+    let start_time = timer::read_cpu_timer();
+    let parsed_input = match parse_input(args, &input) {
+        Ok(parsed_input) => parsed_input,
+        Err(e) => {
+            eprintln!("Unable to parse {path}: {e}");
+            process::exit(1);
+        }
+    };
+    let mid_time = timer::read_cpu_timer();
+    drop(reporter);
 
-fn main() {
-    let input = fs::read_to_string("input.json").unwrap();
+    if let Some(seconds) = args.repeat {
+        repeat(args, &input, &parsed_input, seconds);
+        return;
+    }
 
-    let start_time = Instant::now();
-    //let parsed_input = serde_json::from_str::<Pairs>(input.as_str()).unwrap();
-    let parsed_input = parse(&input);
-    let mid_time = Instant::now();
+    if parsed_input.pairs.is_empty() {
+        no_pairs(path);
+    }
 
-    let sum = parsed_input
-        .pairs
-        .par_iter()
-        .map(haversine_of_degrees)
-        .sum::<f32>();
+    let sum = sum_haversines(args, &parsed_input);
 
-    let average = sum / parsed_input.pairs.len() as f32;
+    let average = sum / parsed_input.pairs.len() as f64;
 
-    let end_time = Instant::now();
+    let end_time = timer::read_cpu_timer();
 
-    println!("Result: {average}");
-    println!(
-        "Input = {} seconds",
-        (mid_time - start_time).as_millis() as f32 / 1000.0
-    );
-    println!(
-        "Math = {} seconds",
-        (end_time - mid_time).as_millis() as f32 / 1000.0
-    );
-    println!(
-        "Total = {} seconds",
-        (end_time - start_time).as_millis() as f32 / 1000.0
-    );
-    println!(
-        "Throughput = {} haversines/second",
-        parsed_input.pairs.len() as f32 / (end_time - start_time).as_secs_f32()
+    report(
+        args,
+        path,
+        average,
+        parsed_input.pairs.len(),
+        input.len(),
+        compressed_bytes,
+        (start_time, Some(mid_time), end_time),
     );
+
+    // Run after timing so the reference parse doesn't count towards the numbers above
+    if args.validate {
+        if let Err(e) = validate(&input, &parsed_input) {
+            eprintln!("Validation failed: {e}");
+            process::exit(1);
+        }
+        if args.output_format == OutputFormat::Plain {
+            println!("Validated {} pairs", parsed_input.pairs.len());
+        }
+    }
 }
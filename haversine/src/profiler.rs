@@ -0,0 +1,148 @@
+// Accumulates cycles per named block. Blocks nest, time spent in a child block counts towards
+// the parent's inclusive time but not its exclusive time. Each thread records into its own
+// profiler which are merged when printing, so blocks running on rayon threads are summed across
+// them. Without the profiler feature profile_block! expands to nothing
+
+#[cfg(feature = "profiler")]
+macro_rules! profile_block {
+    ($name:expr) => {
+        let _profile_guard = crate::profiler::ProfileGuard::new($name);
+    };
+}
+
+#[cfg(not(feature = "profiler"))]
+macro_rules! profile_block {
+    ($name:expr) => {};
+}
+
+#[cfg(feature = "profiler")]
+pub use enabled::*;
+
+#[cfg(feature = "profiler")]
+mod enabled {
+    use crate::timer::read_cpu_timer;
+    use std::{cell::RefCell, sync::Mutex};
+
+    #[derive(Clone, Copy)]
+    struct Anchor {
+        name: &'static str,
+        hits: u64,
+        inclusive: u64,
+        exclusive: u64,
+    }
+
+    struct Frame {
+        anchor: usize,
+        start: u64,
+        children: u64,
+    }
+
+    #[derive(Default)]
+    struct ThreadProfiler {
+        anchors: Vec<Anchor>,
+        stack: Vec<Frame>,
+    }
+
+    thread_local! {
+        static PROFILER: RefCell<ThreadProfiler> = RefCell::new(ThreadProfiler::default());
+    }
+
+    static MERGED: Mutex<Vec<Anchor>> = Mutex::new(Vec::new());
+
+    pub struct ProfileGuard;
+
+    impl ProfileGuard {
+        pub fn new(name: &'static str) -> ProfileGuard {
+            PROFILER.with(|profiler| {
+                let mut profiler = profiler.borrow_mut();
+
+                let anchor = match profiler.anchors.iter().position(|a| a.name == name) {
+                    Some(anchor) => anchor,
+                    None => {
+                        profiler.anchors.push(Anchor {
+                            name,
+                            hits: 0,
+                            inclusive: 0,
+                            exclusive: 0,
+                        });
+                        profiler.anchors.len() - 1
+                    }
+                };
+
+                profiler.stack.push(Frame {
+                    anchor,
+                    start: read_cpu_timer(),
+                    children: 0,
+                });
+            });
+
+            ProfileGuard
+        }
+    }
+
+    impl Drop for ProfileGuard {
+        fn drop(&mut self) {
+            let end = read_cpu_timer();
+
+            PROFILER.with(|profiler| {
+                let mut profiler = profiler.borrow_mut();
+
+                let frame = profiler.stack.pop().unwrap();
+                let elapsed = end - frame.start;
+
+                let anchor = &mut profiler.anchors[frame.anchor];
+                anchor.hits += 1;
+                anchor.inclusive += elapsed;
+                anchor.exclusive += elapsed - frame.children;
+
+                if let Some(parent) = profiler.stack.last_mut() {
+                    parent.children += elapsed;
+                }
+            });
+        }
+    }
+
+    // Moves this thread's counts into the merged set
+    fn flush() {
+        let anchors = PROFILER.with(|profiler| std::mem::take(&mut profiler.borrow_mut().anchors));
+
+        let mut merged = MERGED.lock().unwrap();
+        for anchor in anchors {
+            match merged.iter_mut().find(|a| a.name == anchor.name) {
+                Some(a) => {
+                    a.hits += anchor.hits;
+                    a.inclusive += anchor.inclusive;
+                    a.exclusive += anchor.exclusive;
+                }
+                None => merged.push(anchor),
+            }
+        }
+    }
+
+    // Prints each block as a share of total_cycles, which should be measured on the calling thread
+    pub fn print(total_cycles: u64, cpu_frequency: u64) {
+        rayon::broadcast(|_| flush());
+        flush();
+
+        let percent = |cycles: u64| 100.0 * cycles as f64 / total_cycles as f64;
+
+        println!(
+            "Profile: {total_cycles} cycles, {} seconds",
+            total_cycles as f64 / cpu_frequency as f64
+        );
+        let mut anchors = MERGED.lock().unwrap();
+        anchors.sort_by_key(|anchor| std::cmp::Reverse(anchor.inclusive));
+
+        for anchor in anchors.iter() {
+            println!(
+                "  {}[{}]: {} ({:.2}%), exclusive {} ({:.2}%)",
+                anchor.name,
+                anchor.hits,
+                anchor.inclusive,
+                percent(anchor.inclusive),
+                anchor.exclusive,
+                percent(anchor.exclusive)
+            );
+        }
+    }
+}
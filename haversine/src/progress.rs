@@ -0,0 +1,72 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// Records handled so far, hot loops add to it in batches of BATCH so counting stays cheap
+static RECORDS: AtomicUsize = AtomicUsize::new(0);
+
+pub const BATCH: usize = 1 << 16;
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn add(records: usize) {
+    RECORDS.fetch_add(records, Ordering::Relaxed);
+}
+
+// Prints the number of records counted and the rate since the last print to stderr every
+// INTERVAL, until dropped
+pub struct Reporter {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reporter {
+    pub fn start(label: &'static str) -> Reporter {
+        let done = Arc::new(AtomicBool::new(false));
+
+        let thread = thread::spawn({
+            let done = done.clone();
+            move || {
+                let mut last = (Instant::now(), RECORDS.load(Ordering::Relaxed));
+
+                loop {
+                    thread::park_timeout(INTERVAL.saturating_sub(last.0.elapsed()));
+                    if done.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if last.0.elapsed() < INTERVAL {
+                        continue;
+                    }
+
+                    let now = (Instant::now(), RECORDS.load(Ordering::Relaxed));
+                    eprintln!(
+                        "{label} {} million records, {:.2} million/s",
+                        now.1 / 1_000_000,
+                        (now.1 - last.1) as f64 / (now.0 - last.0).as_secs_f64() / 1e6
+                    );
+                    last = now;
+                }
+            }
+        });
+
+        Reporter {
+            done,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            thread.join().unwrap();
+        }
+    }
+}
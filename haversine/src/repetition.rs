@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+// Runs a test over and over until no new fastest time has been seen for try_for. The minimum is
+// the best estimate of what the code costs without interference from the rest of the machine
+pub struct RepetitionTester {
+    name: &'static str,
+    bytes: u64,
+    try_for: Duration,
+    last_new_min: Instant,
+    start: Option<Instant>,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+    count: u32,
+}
+
+impl RepetitionTester {
+    pub fn new(name: &'static str, bytes: u64, try_for: Duration) -> RepetitionTester {
+        RepetitionTester {
+            name,
+            bytes,
+            try_for,
+            last_new_min: Instant::now(),
+            start: None,
+            min: Duration::MAX,
+            max: Duration::ZERO,
+            total: Duration::ZERO,
+            count: 0,
+        }
+    }
+
+    pub fn is_testing(&self) -> bool {
+        self.last_new_min.elapsed() < self.try_for
+    }
+
+    pub fn begin_time(&mut self) {
+        debug_assert!(self.start.is_none(), "begin_time called twice");
+        self.start = Some(Instant::now());
+    }
+
+    pub fn end_time(&mut self) {
+        let elapsed = self
+            .start
+            .take()
+            .expect("end_time without begin_time")
+            .elapsed();
+
+        if elapsed < self.min {
+            self.min = elapsed;
+            self.last_new_min = Instant::now();
+        }
+        self.max = self.max.max(elapsed);
+        self.total += elapsed;
+        self.count += 1;
+    }
+
+    fn print_line(&self, label: &str, time: Duration) {
+        let seconds = time.as_secs_f64();
+        println!(
+            "  {label}: {:.3} ms, {:.3} GB/s",
+            seconds * 1000.0,
+            self.bytes as f64 / seconds / (1024.0 * 1024.0 * 1024.0)
+        );
+    }
+
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    pub fn print(&self) {
+        if self.count == 0 {
+            return;
+        }
+
+        println!("{} ({} runs):", self.name, self.count);
+        self.print_line("Min", self.min);
+        self.print_line("Max", self.max);
+        self.print_line("Avg", self.total / self.count);
+    }
+}
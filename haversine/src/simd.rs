@@ -0,0 +1,247 @@
+use crate::{sum_chunks, CompensatedSum, Pair};
+use std::arch::x86_64::{
+    __m256, _mm256_add_ps, _mm256_and_ps, _mm256_andnot_ps, _mm256_blendv_ps, _mm256_cmp_ps,
+    _mm256_div_ps, _mm256_loadu_ps, _mm256_min_ps, _mm256_mul_ps, _mm256_or_ps, _mm256_set1_ps,
+    _mm256_sqrt_ps, _mm256_storeu_ps, _mm256_sub_ps, _CMP_GT_OQ,
+};
+
+pub const LANES: usize = 8;
+
+#[target_feature(enable = "avx")]
+unsafe fn splat(value: f32) -> __m256 {
+    _mm256_set1_ps(value)
+}
+
+// Evaluates the polynomial with coefficients from the highest power down at x
+#[target_feature(enable = "avx")]
+unsafe fn polynomial(x: __m256, coefficients: &[f32]) -> __m256 {
+    let mut result = splat(coefficients[0]);
+    for c in &coefficients[1..] {
+        result = _mm256_add_ps(_mm256_mul_ps(result, x), splat(*c));
+    }
+    result
+}
+
+#[target_feature(enable = "avx")]
+unsafe fn abs(x: __m256) -> __m256 {
+    _mm256_andnot_ps(splat(-0.0), x)
+}
+
+// Taylor series for sin(x) / x in x^2, accurate to f32 on [-pi/2, pi/2]
+const SIN_COEFFICIENTS: [f32; 6] = [
+    -1.0 / 39916800.0,
+    1.0 / 362880.0,
+    -1.0 / 5040.0,
+    1.0 / 120.0,
+    -1.0 / 6.0,
+    1.0,
+];
+
+// Taylor series for cos(x) in x^2, accurate to f32 on [-pi/2, pi/2]
+const COS_COEFFICIENTS: [f32; 7] = [
+    1.0 / 479001600.0,
+    -1.0 / 3628800.0,
+    1.0 / 40320.0,
+    -1.0 / 720.0,
+    1.0 / 24.0,
+    -1.0 / 2.0,
+    1.0,
+];
+
+// Cephes asinf minimax polynomial for (asin(x) - x) / x^3 in x^2, on [0, 0.5]
+const ASIN_COEFFICIENTS: [f32; 5] = [
+    4.216_32e-2,
+    2.418_131e-2,
+    4.547_002_6e-2,
+    7.495_300_3e-2,
+    1.666_675_2e-1,
+];
+
+// For x in [-pi, pi], folded into [-pi/2, pi/2] with sin(x) = sin(pi - x)
+#[target_feature(enable = "avx")]
+unsafe fn sin(x: __m256) -> __m256 {
+    let half_pi = splat(std::f32::consts::FRAC_PI_2);
+    let pi = splat(std::f32::consts::PI);
+
+    let sign = _mm256_and_ps(x, splat(-0.0));
+    let folded = _mm256_or_ps(_mm256_sub_ps(pi, abs(x)), sign);
+    let x = _mm256_blendv_ps(x, folded, _mm256_cmp_ps::<_CMP_GT_OQ>(abs(x), half_pi));
+
+    let x2 = _mm256_mul_ps(x, x);
+    _mm256_mul_ps(polynomial(x2, &SIN_COEFFICIENTS), x)
+}
+
+// For x in [-pi/2, pi/2]
+#[target_feature(enable = "avx")]
+unsafe fn cos(x: __m256) -> __m256 {
+    polynomial(_mm256_mul_ps(x, x), &COS_COEFFICIENTS)
+}
+
+// For x in [0, 1], above 0.5 uses asin(x) = pi/2 - 2 asin(sqrt((1 - x) / 2))
+#[target_feature(enable = "avx")]
+unsafe fn asin(x: __m256) -> __m256 {
+    let half = splat(0.5);
+    let large = _mm256_cmp_ps::<_CMP_GT_OQ>(x, half);
+
+    let reduced = _mm256_sqrt_ps(_mm256_mul_ps(half, _mm256_sub_ps(splat(1.0), x)));
+    let t = _mm256_blendv_ps(x, reduced, large);
+
+    let t2 = _mm256_mul_ps(t, t);
+    let small_result = _mm256_add_ps(
+        _mm256_mul_ps(_mm256_mul_ps(polynomial(t2, &ASIN_COEFFICIENTS), t2), t),
+        t,
+    );
+    let large_result = _mm256_sub_ps(
+        splat(std::f32::consts::FRAC_PI_2),
+        _mm256_add_ps(small_result, small_result),
+    );
+
+    _mm256_blendv_ps(small_result, large_result, large)
+}
+
+#[target_feature(enable = "avx")]
+unsafe fn radians(degrees: __m256) -> __m256 {
+    _mm256_mul_ps(degrees, splat(std::f32::consts::PI / 180.0))
+}
+
+// Same formula as haversine_of_degrees for LANES pairs at once, in f32
+#[target_feature(enable = "avx")]
+unsafe fn haversine_avx(pairs: &[Pair; LANES], radius: f32) -> [f32; LANES] {
+    let lane =
+        |f: fn(&Pair) -> f64| -> [f32; LANES] { std::array::from_fn(|i| f(&pairs[i]) as f32) };
+    let (x0, y0, x1, y1) = (
+        lane(|p| p.x0),
+        lane(|p| p.y0),
+        lane(|p| p.x1),
+        lane(|p| p.y1),
+    );
+
+    let mut result = [0.0; LANES];
+
+    let x0 = _mm256_loadu_ps(x0.as_ptr());
+    let y0 = _mm256_loadu_ps(y0.as_ptr());
+    let x1 = _mm256_loadu_ps(x1.as_ptr());
+    let y1 = _mm256_loadu_ps(y1.as_ptr());
+
+    let two = splat(2.0);
+
+    let dy = radians(_mm256_sub_ps(y1, y0));
+    let dx = radians(_mm256_sub_ps(x1, x0));
+    let y0 = radians(y0);
+    let y1 = radians(y1);
+
+    let sin_dy = sin(_mm256_div_ps(dy, two));
+    let sin_dx = sin(_mm256_div_ps(dx, two));
+
+    let root_term = _mm256_add_ps(
+        _mm256_mul_ps(sin_dy, sin_dy),
+        _mm256_mul_ps(
+            _mm256_mul_ps(cos(y0), cos(y1)),
+            _mm256_mul_ps(sin_dx, sin_dx),
+        ),
+    );
+
+    // Rounding can push the term just past 1, outside of the asin approximation
+    let root_term = _mm256_min_ps(root_term, splat(1.0));
+
+    let scale = splat(2.0 * radius);
+    let distance = _mm256_mul_ps(scale, asin(_mm256_sqrt_ps(root_term)));
+
+    _mm256_storeu_ps(result.as_mut_ptr(), distance);
+
+    result
+}
+
+pub fn has_avx() -> bool {
+    is_x86_feature_detected!("avx")
+}
+
+// haversine_avx on CPUs with AVX, otherwise the scalar version rounded to f32
+pub fn haversine_of_degrees(pairs: &[Pair; LANES], radius: f32) -> [f32; LANES] {
+    if has_avx() {
+        // Only reached with AVX
+        unsafe { haversine_avx(pairs, radius) }
+    } else {
+        std::array::from_fn(|i| crate::haversine_of_degrees(&pairs[i], radius as f64) as f32)
+    }
+}
+
+// Sums the distances like crate::sum_distances, with the pairs that don't fill a batch done by
+// the scalar version. The chunks are a multiple of LANES so only the last one has any. Without
+// AVX it is crate::sum_haversines
+pub fn sum_haversines(pairs: &[Pair], radius: f64) -> f64 {
+    if !has_avx() {
+        return crate::sum_haversines(pairs, radius);
+    }
+
+    sum_chunks(pairs, 1, |chunk| {
+        let mut sum = CompensatedSum::default();
+        let mut batches = chunk.chunks_exact(LANES);
+
+        for batch in &mut batches {
+            // Checked for AVX above
+            let distances = unsafe { haversine_avx(batch.try_into().unwrap(), radius as f32) };
+            for distance in distances {
+                sum.add(distance as f64);
+            }
+        }
+
+        for pair in batches.remainder() {
+            sum.add(crate::haversine_of_degrees(pair, radius));
+        }
+
+        sum
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::random_pairs;
+
+    #[test]
+    fn agrees_with_the_scalar_version() {
+        // More than a chunk, with pairs left over that don't fill a batch
+        let pairs = random_pairs(40_003, 45);
+        let simd = sum_haversines(&pairs, crate::EARTH_RADIUS_KM);
+        let scalar = crate::sum_haversines(&pairs, crate::EARTH_RADIUS_KM);
+
+        assert!(
+            ((simd - scalar) / scalar).abs() < 1e-6,
+            "{simd} != {scalar}"
+        );
+    }
+
+    // f32 loses the most close to the antipode, where asin is steep
+    #[test]
+    fn every_lane_agrees_with_the_scalar_version() {
+        for batch in random_pairs(4096, 45).chunks_exact(LANES) {
+            let distances =
+                haversine_of_degrees(batch.try_into().unwrap(), crate::EARTH_RADIUS_KM as f32);
+            for (pair, simd) in batch.iter().zip(distances) {
+                let scalar = crate::haversine_of_degrees(pair, crate::EARTH_RADIUS_KM);
+                assert!(
+                    (simd as f64 - scalar).abs() <= scalar * 1e-3,
+                    "{simd} != {scalar}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn same_on_any_number_of_threads() {
+        let pairs = random_pairs(100_000, 45);
+        let sum = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| sum_haversines(&pairs, crate::EARTH_RADIUS_KM))
+        };
+
+        let one = sum(1);
+        for threads in [2, 3, 8] {
+            assert_eq!(sum(threads).to_bits(), one.to_bits());
+        }
+    }
+}
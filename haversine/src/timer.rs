@@ -0,0 +1,31 @@
+use std::time::{Duration, Instant};
+
+// Time stamp counter, ticks at a fixed rate independent of the current clock speed
+#[cfg(target_arch = "x86_64")]
+pub fn read_cpu_timer() -> u64 {
+    unsafe { std::arch::x86_64::_rdtsc() }
+}
+
+// Without a cycle counter fall back to nanoseconds since the first read
+#[cfg(not(target_arch = "x86_64"))]
+pub fn read_cpu_timer() -> u64 {
+    use std::sync::OnceLock;
+
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+const CALIBRATION_TIME: Duration = Duration::from_millis(100);
+
+// Ticks of read_cpu_timer per second, measured against the OS clock
+pub fn estimate_cpu_frequency() -> u64 {
+    let os_start = Instant::now();
+    let cpu_start = read_cpu_timer();
+
+    while os_start.elapsed() < CALIBRATION_TIME {}
+
+    let cpu_elapsed = read_cpu_timer() - cpu_start;
+    let os_elapsed = os_start.elapsed();
+
+    (cpu_elapsed as f64 / os_elapsed.as_secs_f64()) as u64
+}
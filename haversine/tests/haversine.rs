@@ -0,0 +1,99 @@
+use pap::{parse, parse_binary, sum_haversines, Pair, Pairs, EARTH_RADIUS_KM};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const PAIRS: usize = 10_000;
+const SEED: u64 = 0;
+
+// Average in km of the PAIRS pairs generated from SEED, computed when this was written
+const GOLDEN_AVERAGE: f64 = 9_970.944_678_426_082;
+
+// The averages only differ by how the formulas and the summations round, which stays many orders
+// of magnitude below this
+const EPSILON: f64 = 1e-6;
+
+// Great circle distance from the cross and dot products of the points as unit vectors, sharing
+// nothing with the haversine beyond the idea of a sphere
+fn reference_distance(p: &Pair, radius: f64) -> f64 {
+    let unit = |x: f64, y: f64| {
+        let (x, y) = (x.to_radians(), y.to_radians());
+        [y.cos() * x.cos(), y.cos() * x.sin(), y.sin()]
+    };
+    let a = unit(p.x0, p.y0);
+    let b = unit(p.x1, p.y1);
+
+    let cross = [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ];
+    let sin = cross.iter().map(|c| c * c).sum::<f64>().sqrt();
+    let cos = a.iter().zip(&b).map(|(a, b)| a * b).sum::<f64>();
+
+    radius * sin.atan2(cos)
+}
+
+fn generate() -> Pairs {
+    let mut rng = StdRng::seed_from_u64(SEED);
+
+    Pairs {
+        pairs: (0..PAIRS)
+            .map(|_| Pair {
+                x0: rng.gen_range(-180.0..180.0),
+                y0: rng.gen_range(-90.0..90.0),
+                x1: rng.gen_range(-180.0..180.0),
+                y1: rng.gen_range(-90.0..90.0),
+            })
+            .collect(),
+    }
+}
+
+// Checks the fast parser and the compensated haversine sum against serde_json and an independent
+// formula on a fixed set of pairs, and both against the known average
+#[test]
+fn matches_the_reference_and_the_golden_average() {
+    let json = serde_json::to_vec(&generate()).unwrap();
+
+    let parsed = parse(&json, 4).unwrap();
+    assert_eq!(parsed.pairs.len(), PAIRS);
+    let fast = sum_haversines(&parsed.pairs, EARTH_RADIUS_KM) / parsed.pairs.len() as f64;
+
+    let reference = serde_json::from_slice::<Pairs>(&json).unwrap();
+    let reference = reference
+        .pairs
+        .iter()
+        .map(|p| reference_distance(p, EARTH_RADIUS_KM))
+        .sum::<f64>()
+        / reference.pairs.len() as f64;
+
+    for (name, average) in [("Reference", reference), ("Golden", GOLDEN_AVERAGE)] {
+        assert!(
+            (fast - average).abs() <= EPSILON,
+            "{name} {average} differs from fast {fast} by {}",
+            (fast - average).abs()
+        );
+    }
+}
+
+// The same pairs as a flat array of little endian x0, y0, x1, y1 records
+fn binary(pairs: &Pairs) -> Vec<u8> {
+    pairs
+        .pairs
+        .iter()
+        .flat_map(|p| [p.x0, p.y0, p.x1, p.y1])
+        .flat_map(f64::to_le_bytes)
+        .collect()
+}
+
+// Both formats hold the exact f64s, so the averages match to the bit
+#[test]
+fn binary_and_json_give_the_same_average() {
+    let pairs = generate();
+    let json = parse(&serde_json::to_vec(&pairs).unwrap(), 4).unwrap();
+    let binary = parse_binary(&binary(&pairs)).unwrap();
+
+    assert_eq!(binary.pairs.len(), PAIRS);
+    assert_eq!(
+        sum_haversines(&binary.pairs, EARTH_RADIUS_KM).to_bits(),
+        sum_haversines(&json.pairs, EARTH_RADIUS_KM).to_bits()
+    );
+}
@@ -0,0 +1,70 @@
+use pap86::decode;
+use std::{
+    env, fs,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+static LISTINGS_DIR: &str = "../perfaware/part1";
+
+// Copies of the listing decoded per run, the listings are only a few dozen bytes each
+const COPIES: usize = 10_000;
+
+const TRY_FOR: Duration = Duration::from_secs(3);
+
+// The largest listing that decodes, none of them jump so the copies can be laid end to end
+fn input() -> Vec<u8> {
+    let listing = fs::read_dir(LISTINGS_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_none())
+        .map(|path| fs::read(path).unwrap())
+        .filter(|bytes| decode(bytes).is_ok())
+        .max_by_key(|bytes| bytes.len())
+        .expect("No decodable listing");
+
+    listing.repeat(COPIES)
+}
+
+// cargo bench passes --bench, without it (cargo test --benches) the decode runs once as a smoke
+// test
+fn main() {
+    let try_for = if env::args().any(|arg| arg == "--bench") {
+        TRY_FOR
+    } else {
+        Duration::ZERO
+    };
+
+    let input = input();
+
+    let start = Instant::now();
+    let mut min = Duration::MAX;
+    let mut total = Duration::ZERO;
+    let mut count = 0;
+
+    loop {
+        let run = Instant::now();
+        black_box(decode(&input).unwrap());
+        let elapsed = run.elapsed();
+
+        min = min.min(elapsed);
+        total += elapsed;
+        count += 1;
+
+        if start.elapsed() >= try_for {
+            break;
+        }
+    }
+
+    let print = |label: &str, time: Duration| {
+        println!(
+            "  {label}: {:.3} ms, {:.3} MB/s",
+            time.as_secs_f64() * 1000.0,
+            input.len() as f64 / time.as_secs_f64() / (1024.0 * 1024.0)
+        )
+    };
+
+    println!("Decode {} bytes ({count} runs):", input.len());
+    print("Min", min);
+    print("Avg", total / count);
+}
@@ -0,0 +1,120 @@
+use crate::{EffectiveAddressFormula, Instruction, Operand, Register};
+
+// Estimated 8086 clocks for an instruction, ea is the effective address calculation on top of base
+pub struct Clocks {
+    pub base: u32,
+    pub ea: u32,
+}
+
+impl Clocks {
+    pub fn total(&self) -> u32 {
+        self.base + self.ea
+    }
+}
+
+// Includes the 2 clocks taken by a segment override
+fn effective_address_clocks(operand: &Operand) -> Option<u32> {
+    let segment_clocks = |segment: &Option<_>| if segment.is_some() { 2 } else { 0 };
+
+    match operand {
+        Operand::MemDirect { segment, .. } => Some(6 + segment_clocks(segment)),
+        Operand::Mem {
+            formula,
+            displacement,
+            segment,
+        } => Some(
+            segment_clocks(segment)
+                + match (formula, displacement.is_some()) {
+                    (
+                        EffectiveAddressFormula::Si
+                        | EffectiveAddressFormula::Di
+                        | EffectiveAddressFormula::Bp
+                        | EffectiveAddressFormula::Bx,
+                        false,
+                    ) => 5,
+                    (
+                        EffectiveAddressFormula::Si
+                        | EffectiveAddressFormula::Di
+                        | EffectiveAddressFormula::Bp
+                        | EffectiveAddressFormula::Bx,
+                        true,
+                    ) => 9,
+                    (
+                        EffectiveAddressFormula::BpPlusDi | EffectiveAddressFormula::BxPlusSi,
+                        false,
+                    ) => 7,
+                    (
+                        EffectiveAddressFormula::BpPlusSi | EffectiveAddressFormula::BxPlusDi,
+                        false,
+                    ) => 8,
+                    (
+                        EffectiveAddressFormula::BpPlusDi | EffectiveAddressFormula::BxPlusSi,
+                        true,
+                    ) => 11,
+                    (
+                        EffectiveAddressFormula::BpPlusSi | EffectiveAddressFormula::BxPlusDi,
+                        true,
+                    ) => 12,
+                },
+        ),
+        _ => None,
+    }
+}
+
+fn is_accumulator(operand: &Operand) -> bool {
+    matches!(operand, Operand::Register(Register::AX | Register::AL))
+}
+
+// Base clocks for the operand shapes of a two operand instruction, in the order
+// (reg, reg), (reg, mem), (mem, reg), (reg, imm), (mem, imm)
+fn two_operand_clocks(dst: &Operand, src: &Operand, table: [u32; 5]) -> Option<Clocks> {
+    let base = match (dst, src) {
+        (
+            Operand::Register(_) | Operand::SegmentRegister(_),
+            Operand::Register(_) | Operand::SegmentRegister(_),
+        ) => table[0],
+        (Operand::Register(_), Operand::Immediate { .. }) => table[3],
+        (Operand::Register(_) | Operand::SegmentRegister(_), _) => table[1],
+        (_, Operand::Register(_) | Operand::SegmentRegister(_)) => table[2],
+        (_, Operand::Immediate { .. }) => table[4],
+        _ => return None,
+    };
+
+    let ea = effective_address_clocks(dst)
+        .or_else(|| effective_address_clocks(src))
+        .unwrap_or(0);
+
+    Some(Clocks { base, ea })
+}
+
+pub fn estimate(instruction: &Instruction) -> Option<Clocks> {
+    match instruction {
+        // The accumulator forms with a direct address are what NASM emits, so assume them
+        Instruction::Mov {
+            dst,
+            src: Operand::MemDirect { .. },
+        } if is_accumulator(dst) => Some(Clocks { base: 10, ea: 0 }),
+        Instruction::Mov {
+            dst: Operand::MemDirect { .. },
+            src,
+        } if is_accumulator(src) => Some(Clocks { base: 10, ea: 0 }),
+        Instruction::Mov { dst, src } => two_operand_clocks(dst, src, [2, 8, 9, 4, 10]),
+        Instruction::Add { dst, src }
+        | Instruction::Sub { dst, src }
+        | Instruction::Or { dst, src }
+        | Instruction::Adc { dst, src }
+        | Instruction::Sbb { dst, src }
+        | Instruction::And { dst, src }
+        | Instruction::Xor { dst, src } => two_operand_clocks(dst, src, [3, 9, 16, 4, 17]),
+        Instruction::Cmp { dst, src } => two_operand_clocks(dst, src, [3, 9, 9, 4, 10]),
+        Instruction::LoadPointer { src, .. } => Some(Clocks {
+            base: 16,
+            ea: effective_address_clocks(src).unwrap_or(0),
+        }),
+        Instruction::NoOperand { mnemonic } => Some(Clocks {
+            base: if *mnemonic == "nop" { 3 } else { 2 },
+            ea: 0,
+        }),
+        _ => None,
+    }
+}
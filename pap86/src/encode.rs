@@ -0,0 +1,196 @@
+use crate::{EffectiveAddressFormula, Instruction, Operand, Register};
+
+fn is_wide(reg: Register) -> bool {
+    reg as u8 & 0b1000 > 0
+}
+
+fn segment_prefix(operand: &Operand) -> Option<u8> {
+    match operand {
+        Operand::Mem { segment, .. } | Operand::MemDirect { segment, .. } => {
+            segment.map(|segment| 0b0010_0110 | ((segment as u8) << 3))
+        }
+        _ => None,
+    }
+}
+
+fn mod_reg_rm(rm: &Operand, reg: u8, out: &mut Vec<u8>) -> Result<(), String> {
+    match rm {
+        Operand::Register(rm) => out.push(0b1100_0000 | (reg << 3) | (*rm as u8 & 0b111)),
+        Operand::MemDirect { address, .. } => {
+            out.push((reg << 3) | 0b110);
+            out.extend_from_slice(&address.to_le_bytes());
+        }
+        Operand::Mem {
+            formula,
+            displacement,
+            ..
+        } => match (formula, displacement) {
+            // [bp] has no mod 00 form, that encoding is the direct address
+            (EffectiveAddressFormula::Bp, None) => {
+                out.push(0b0100_0000 | (reg << 3) | *formula as u8);
+                out.push(0);
+            }
+            (_, None) => out.push((reg << 3) | *formula as u8),
+            (_, Some(displacement)) if i8::try_from(*displacement).is_ok() => {
+                out.push(0b0100_0000 | (reg << 3) | *formula as u8);
+                out.push(*displacement as u8);
+            }
+            (_, Some(displacement)) => {
+                out.push(0b1000_0000 | (reg << 3) | *formula as u8);
+                out.extend_from_slice(&displacement.to_le_bytes());
+            }
+        },
+        _ => Err(format!("Invalid r/m operand: {rm}"))?,
+    }
+
+    Ok(())
+}
+
+fn immediate(value: u16, wide: bool, out: &mut Vec<u8>) {
+    if wide {
+        out.extend_from_slice(&value.to_le_bytes());
+    } else {
+        out.push(value as u8);
+    }
+}
+
+fn encode_mov(dst: &Operand, src: &Operand, out: &mut Vec<u8>) -> Result<(), String> {
+    if let Some(prefix) = segment_prefix(dst).or_else(|| segment_prefix(src)) {
+        out.push(prefix);
+    }
+
+    match (dst, src) {
+        (
+            Operand::Register(reg @ (Register::AX | Register::AL)),
+            Operand::MemDirect { address, .. },
+        ) => {
+            out.push(0b1010_0000 | is_wide(*reg) as u8);
+            out.extend_from_slice(&address.to_le_bytes());
+        }
+        (
+            Operand::MemDirect { address, .. },
+            Operand::Register(reg @ (Register::AX | Register::AL)),
+        ) => {
+            out.push(0b1010_0010 | is_wide(*reg) as u8);
+            out.extend_from_slice(&address.to_le_bytes());
+        }
+        (Operand::Register(reg), Operand::Immediate { value, .. }) => {
+            out.push(0b1011_0000 | *reg as u8);
+            immediate(*value, is_wide(*reg), out);
+        }
+        (_, Operand::Immediate { value, wide, .. }) => {
+            out.push(0b1100_0110 | *wide as u8);
+            mod_reg_rm(dst, 0b000, out)?;
+            immediate(*value, *wide, out);
+        }
+        (Operand::SegmentRegister(sr), _) => {
+            out.push(0b1000_1110);
+            mod_reg_rm(src, *sr as u8, out)?;
+        }
+        (_, Operand::SegmentRegister(sr)) => {
+            out.push(0b1000_1100);
+            mod_reg_rm(dst, *sr as u8, out)?;
+        }
+        // Register to register uses d = 0, with reg as the source
+        (_, Operand::Register(reg)) => {
+            out.push(0b1000_1000 | is_wide(*reg) as u8);
+            mod_reg_rm(dst, *reg as u8 & 0b111, out)?;
+        }
+        (Operand::Register(reg), _) => {
+            out.push(0b1000_1010 | is_wide(*reg) as u8);
+            mod_reg_rm(src, *reg as u8 & 0b111, out)?;
+        }
+        _ => Err(format!("Invalid mov operands: {dst}, {src}"))?,
+    }
+
+    Ok(())
+}
+
+// The operation number in bits 5..3 of the opcode and in the reg field of the immediate group
+fn arithmetic_op(instruction: &Instruction) -> Option<(u8, &Operand, &Operand)> {
+    match instruction {
+        Instruction::Add { dst, src } => Some((0b000, dst, src)),
+        Instruction::Or { dst, src } => Some((0b001, dst, src)),
+        Instruction::Adc { dst, src } => Some((0b010, dst, src)),
+        Instruction::Sbb { dst, src } => Some((0b011, dst, src)),
+        Instruction::And { dst, src } => Some((0b100, dst, src)),
+        Instruction::Sub { dst, src } => Some((0b101, dst, src)),
+        Instruction::Xor { dst, src } => Some((0b110, dst, src)),
+        Instruction::Cmp { dst, src } => Some((0b111, dst, src)),
+        _ => None,
+    }
+}
+
+fn encode_arithmetic(
+    op: u8,
+    dst: &Operand,
+    src: &Operand,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    if let Some(prefix) = segment_prefix(dst).or_else(|| segment_prefix(src)) {
+        out.push(prefix);
+    }
+
+    match (dst, src) {
+        (
+            _,
+            Operand::Immediate {
+                value,
+                wide,
+                signed,
+                ..
+            },
+        ) => {
+            // 0x81 immediates decode unsigned, so only signed ones that fit take the 0x83 byte
+            let sign_extended = *wide && *signed && i8::try_from(*value as i16).is_ok();
+
+            match dst {
+                Operand::Register(Register::AX | Register::AL) if !sign_extended => {
+                    out.push((op << 3) | 0b100 | *wide as u8);
+                    immediate(*value, *wide, out);
+                }
+                _ if sign_extended => {
+                    out.push(0b1000_0011);
+                    mod_reg_rm(dst, op, out)?;
+                    out.push(*value as u8);
+                }
+                _ => {
+                    out.push(0b1000_0000 | *wide as u8);
+                    mod_reg_rm(dst, op, out)?;
+                    immediate(*value, *wide, out);
+                }
+            }
+        }
+        // Register to register uses d = 0 like mov, so xor cx, cx is 31 c9
+        (_, Operand::Register(reg)) => {
+            out.push((op << 3) | is_wide(*reg) as u8);
+            mod_reg_rm(dst, *reg as u8 & 0b111, out)?;
+        }
+        (Operand::Register(reg), _) => {
+            out.push((op << 3) | 0b010 | is_wide(*reg) as u8);
+            mod_reg_rm(src, *reg as u8 & 0b111, out)?;
+        }
+        _ => Err(format!("Invalid arithmetic operands: {dst}, {src}"))?,
+    }
+
+    Ok(())
+}
+
+// Picks the same encodings NASM does so decoding an assembled listing and encoding it again
+// reproduces the original bytes
+pub fn encode(instructions: &[Instruction]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+
+    for instruction in instructions {
+        match instruction {
+            Instruction::Mov { dst, src } => encode_mov(dst, src, &mut out)?,
+            Instruction::Db { byte } => out.push(*byte),
+            _ => match arithmetic_op(instruction) {
+                Some((op, dst, src)) => encode_arithmetic(op, dst, src, &mut out)?,
+                None => Err(format!("Unable to encode: {instruction}"))?,
+            },
+        }
+    }
+
+    Ok(out)
+}
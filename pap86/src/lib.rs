@@ -0,0 +1,2414 @@
+use serde::Serialize;
+use std::{
+    cmp::Ordering,
+    fmt::{Display, LowerHex},
+    io::Write,
+};
+use strum_macros::{AsRefStr, FromRepr, IntoStaticStr};
+
+pub mod clocks;
+pub mod encode;
+pub mod sim;
+
+#[derive(Copy, Clone, Debug)]
+enum Opcode {
+    MovRegToRegOrRegToMem,
+    MovImmediateToMem,
+    MovImmediateToReg,
+    MovMemToAcc,
+    MovAccToMem,
+    ImmediateToRegOrMem,
+    ConditionalJump,
+    Loop,
+    PushReg,
+    PopReg,
+    IncDecReg,
+    PushPopSegmentReg,
+    GroupFeFf,
+    PopRegOrMem,
+    MovSegmentReg,
+    StringOp,
+    GroupF6F7,
+    XchgRegOrMem,
+    XchgRegWithAcc,
+    Lea,
+    LoadPointer,
+    InOutFixedPort,
+    InOutVariablePort,
+    CallNear,
+    CallFar,
+    JmpNear,
+    JmpShort,
+    JmpFar,
+    ArithmeticRegToRegOrRegToMem,
+    ArithmeticImmediateToAcc,
+    TestRegToRegOrRegToMem,
+    TestImmediateToAcc,
+    NoOperand,
+}
+
+impl Opcode {
+    fn parse(byte: u8) -> Result<Opcode, String> {
+        if byte & 0b1111_1100 == 0b1000_1000 {
+            return Ok(Opcode::MovRegToRegOrRegToMem);
+        }
+
+        if byte & 0b1111_1110 == 0b1100_0110 {
+            return Ok(Opcode::MovImmediateToMem);
+        }
+
+        if byte & 0b1111_0000 == 0b1011_0000 {
+            return Ok(Opcode::MovImmediateToReg);
+        }
+
+        if byte & 0b1111_1110 == 0b1010_0000 {
+            return Ok(Opcode::MovMemToAcc);
+        }
+
+        if byte & 0b1111_1110 == 0b1010_0010 {
+            return Ok(Opcode::MovAccToMem);
+        }
+
+        if byte & 0b1111_1100 == 0b1000_0000 {
+            return Ok(Opcode::ImmediateToRegOrMem);
+        }
+
+        if byte & 0b1111_0000 == 0b0111_0000 {
+            return Ok(Opcode::ConditionalJump);
+        }
+
+        if byte & 0b1111_1100 == 0b1110_0000 {
+            return Ok(Opcode::Loop);
+        }
+
+        if byte & 0b1111_0000 == 0b0100_0000 {
+            return Ok(Opcode::IncDecReg);
+        }
+
+        if byte & 0b1111_1000 == 0b0101_0000 {
+            return Ok(Opcode::PushReg);
+        }
+
+        if byte & 0b1111_1000 == 0b0101_1000 {
+            return Ok(Opcode::PopReg);
+        }
+
+        // pop cs (0b0000_1111) is left out as NASM refuses to assemble it
+        if byte & 0b1110_0110 == 0b0000_0110 && byte != 0b0000_1111 {
+            return Ok(Opcode::PushPopSegmentReg);
+        }
+
+        if byte & 0b1111_1110 == 0b1111_1110 {
+            return Ok(Opcode::GroupFeFf);
+        }
+
+        if byte == 0b1000_1111 {
+            return Ok(Opcode::PopRegOrMem);
+        }
+
+        if byte & 0b1111_1101 == 0b1000_1100 {
+            return Ok(Opcode::MovSegmentReg);
+        }
+
+        if matches!(
+            byte & 0b1111_1110,
+            0b1010_0100 | 0b1010_0110 | 0b1010_1010 | 0b1010_1100 | 0b1010_1110
+        ) {
+            return Ok(Opcode::StringOp);
+        }
+
+        if byte & 0b1111_1110 == 0b1111_0110 {
+            return Ok(Opcode::GroupF6F7);
+        }
+
+        if byte & 0b1111_1110 == 0b1000_0110 {
+            return Ok(Opcode::XchgRegOrMem);
+        }
+
+        // xchg ax, ax (0b1001_0000) is decoded as nop with the other single byte instructions
+        if byte & 0b1111_1000 == 0b1001_0000 && byte != 0b1001_0000 {
+            return Ok(Opcode::XchgRegWithAcc);
+        }
+
+        if byte == 0b1000_1101 {
+            return Ok(Opcode::Lea);
+        }
+
+        // les and lds
+        if byte & 0b1111_1110 == 0b1100_0100 {
+            return Ok(Opcode::LoadPointer);
+        }
+
+        if byte & 0b1111_0100 == 0b1110_0100 && byte & 0b1000 == 0 {
+            return Ok(Opcode::InOutFixedPort);
+        }
+
+        if byte & 0b1111_0100 == 0b1110_0100 {
+            return Ok(Opcode::InOutVariablePort);
+        }
+
+        // add, or, adc, sbb, and, sub, xor and cmp share one layout with the operation in bits
+        // 5..3, the same numbering as the reg field of the immediate group. The other low bits
+        // are segment pushes and pops, prefixes and BCD adjustments
+        if byte < 0b0100_0000 {
+            match byte & 0b111 {
+                0b000..=0b011 => return Ok(Opcode::ArithmeticRegToRegOrRegToMem),
+                0b100 | 0b101 => return Ok(Opcode::ArithmeticImmediateToAcc),
+                _ => {}
+            }
+        }
+
+        if byte & 0b1111_1110 == 0b1000_0100 {
+            return Ok(Opcode::TestRegToRegOrRegToMem);
+        }
+
+        if byte & 0b1111_1110 == 0b1010_1000 {
+            return Ok(Opcode::TestImmediateToAcc);
+        }
+
+        match byte {
+            0b1110_1000 => return Ok(Opcode::CallNear),
+            0b1001_1010 => return Ok(Opcode::CallFar),
+            0b1110_1001 => return Ok(Opcode::JmpNear),
+            0b1110_1011 => return Ok(Opcode::JmpShort),
+            0b1110_1010 => return Ok(Opcode::JmpFar),
+            _ => {}
+        }
+
+        if no_operand_mnemonic(byte).is_some() {
+            return Ok(Opcode::NoOperand);
+        }
+
+        Err(format!("Invalid opcode: {byte:08b}"))
+    }
+}
+
+// Register from encoding W | REG
+#[derive(AsRefStr, Copy, Clone, Debug, FromRepr, Serialize)]
+#[repr(u8)]
+pub enum Register {
+    AL = 0b0000,
+    CL = 0b0001,
+    DL = 0b0010,
+    BL = 0b0011,
+    AH = 0b0100,
+    CH = 0b0101,
+    DH = 0b0110,
+    BH = 0b0111,
+    AX = 0b1000,
+    CX = 0b1001,
+    DX = 0b1010,
+    BX = 0b1011,
+    SP = 0b1100,
+    BP = 0b1101,
+    SI = 0b1110,
+    DI = 0b1111,
+}
+
+// Segment register from encoding SR
+#[derive(AsRefStr, Copy, Clone, Debug, FromRepr, Serialize)]
+#[repr(u8)]
+pub enum SegmentRegister {
+    ES = 0b00,
+    CS = 0b01,
+    SS = 0b10,
+    DS = 0b11,
+}
+
+#[derive(Copy, Clone, Debug, FromRepr, Serialize)]
+#[repr(u8)]
+pub enum EffectiveAddressFormula {
+    BxPlusSi = 0b000,
+    BxPlusDi = 0b001,
+    BpPlusSi = 0b010,
+    BpPlusDi = 0b011,
+    Si = 0b100,
+    Di = 0b101,
+    Bp = 0b110,
+    Bx = 0b111,
+}
+
+impl Display for EffectiveAddressFormula {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EffectiveAddressFormula::BxPlusSi => write!(f, "bx + si"),
+            EffectiveAddressFormula::BxPlusDi => write!(f, "bx + di"),
+            EffectiveAddressFormula::BpPlusSi => write!(f, "bp + si"),
+            EffectiveAddressFormula::BpPlusDi => write!(f, "bp + di"),
+            EffectiveAddressFormula::Si => write!(f, "si"),
+            EffectiveAddressFormula::Di => write!(f, "di"),
+            EffectiveAddressFormula::Bp => write!(f, "bp"),
+            EffectiveAddressFormula::Bx => write!(f, "bx"),
+        }
+    }
+}
+
+// Numbers in instructions are decimal with {} and hex with {:x}
+fn number(value: i32, hex: bool) -> String {
+    if !hex {
+        format!("{value}")
+    } else if value < 0 {
+        format!("-0x{:x}", value.unsigned_abs())
+    } else {
+        format!("0x{value:x}")
+    }
+}
+
+fn displacement_str(displacement: &Option<i16>, hex: bool) -> String {
+    if let Some(displacement) = displacement {
+        match displacement.cmp(&0) {
+            Ordering::Greater => format!(" + {}", number(*displacement as i32, hex)),
+            Ordering::Less => format!(" - {}", number(displacement.unsigned_abs() as i32, hex)),
+            Ordering::Equal => "".to_string(),
+        }
+    } else {
+        "".to_string()
+    }
+}
+
+fn segment_str(segment: &Option<SegmentRegister>) -> String {
+    if let Some(segment) = segment {
+        format!("{}:", segment.as_ref().to_lowercase())
+    } else {
+        "".to_string()
+    }
+}
+
+// Memory operands of single operand instructions need an explicit size
+fn sized_str(operand: &Operand, wide: bool, hex: bool) -> String {
+    match operand {
+        Operand::Mem { .. } | Operand::MemDirect { .. } => {
+            format!(
+                "{} {}",
+                if wide { "word" } else { "byte" },
+                operand.render(hex)
+            )
+        }
+        _ => operand.render(hex),
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum Operand {
+    Register(Register),
+    SegmentRegister(SegmentRegister),
+    Mem {
+        formula: EffectiveAddressFormula,
+        displacement: Option<i16>,
+        segment: Option<SegmentRegister>,
+    },
+    MemDirect {
+        address: u16,
+        segment: Option<SegmentRegister>,
+    },
+    Immediate {
+        value: u16,
+        wide: bool,
+        needs_size: bool,
+        // Printed as a signed number, all but the immediate group without sign extension are
+        signed: bool,
+    },
+}
+
+impl Operand {
+    fn render(&self, hex: bool) -> String {
+        match self {
+            Operand::Register(reg) => reg.as_ref().to_lowercase(),
+            Operand::SegmentRegister(reg) => reg.as_ref().to_lowercase(),
+            Operand::Mem {
+                formula,
+                displacement,
+                segment,
+            } => {
+                format!(
+                    "{}[{}{}]",
+                    segment_str(segment),
+                    formula,
+                    displacement_str(displacement, hex),
+                )
+            }
+            Operand::MemDirect { address, segment } => {
+                format!("{}[{}]", segment_str(segment), number(*address as i32, hex))
+            }
+            Operand::Immediate {
+                value,
+                wide,
+                needs_size,
+                signed,
+            } => {
+                // Rendered at the encoded width, NASM accepts both and the references use signed
+                let value = match (signed, wide) {
+                    (true, true) => *value as i16 as i32,
+                    (true, false) => *value as u8 as i8 as i32,
+                    (false, true) => *value as i32,
+                    (false, false) => *value as u8 as i32,
+                };
+
+                let signed = number(value, hex);
+
+                if *needs_size {
+                    if *wide {
+                        format!("word {signed}")
+                    } else {
+                        format!("byte {signed}")
+                    }
+                } else {
+                    signed
+                }
+            }
+        }
+    }
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(false))
+    }
+}
+
+impl LowerHex for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(true))
+    }
+}
+
+// Conditional jumps indexed by the low 4 bits of 0b0111_xxxx
+static CONDITIONAL_JUMP_MNEMONICS: [&str; 16] = [
+    "jo", "jno", "jb", "jnb", "je", "jne", "jbe", "ja", "js", "jns", "jp", "jnp", "jl", "jnl",
+    "jle", "jg",
+];
+
+// Loops indexed by the low 2 bits of 0b1110_00xx
+static LOOP_MNEMONICS: [&str; 4] = ["loopnz", "loopz", "loop", "jcxz"];
+
+// Single byte instructions without operands. 0x90 encodes xchg ax, ax, which everyone writes nop
+fn no_operand_mnemonic(byte: u8) -> Option<&'static str> {
+    Some(match byte {
+        0x90 => "nop",
+        0xf4 => "hlt",
+        0xf5 => "cmc",
+        0xf8 => "clc",
+        0xf9 => "stc",
+        0xfa => "cli",
+        0xfb => "sti",
+        0xfc => "cld",
+        0xfd => "std",
+        _ => return None,
+    })
+}
+
+#[derive(AsRefStr, Copy, Clone, Debug, Serialize)]
+#[strum(serialize_all = "lowercase")]
+pub enum RepKind {
+    Rep,
+    Repne,
+}
+
+#[derive(AsRefStr, IntoStaticStr, Copy, Clone, Debug, Serialize)]
+#[strum(serialize_all = "lowercase")]
+pub enum StringOperation {
+    Movs,
+    Cmps,
+    Stos,
+    Lods,
+    Scas,
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum JumpTarget {
+    // Signed displacement from the end of the jump
+    Relative(i16),
+    // A jump to an instruction with a label, still holding the displacement for the simulator
+    Label { label: usize, displacement: i16 },
+}
+
+impl JumpTarget {
+    pub fn displacement(&self) -> i16 {
+        match self {
+            JumpTarget::Relative(displacement) | JumpTarget::Label { displacement, .. } => {
+                *displacement
+            }
+        }
+    }
+}
+
+// NASM's $ is the start of the instruction, while the displacement is relative to the next one
+fn jump_target_str(target: &JumpTarget, len: i16, hex: bool) -> String {
+    match target {
+        JumpTarget::Relative(displacement) => {
+            let relative = displacement.wrapping_add(len);
+            if relative < 0 {
+                format!("${}", number(relative as i32, hex))
+            } else {
+                format!("$+{}", number(relative as i32, hex))
+            }
+        }
+        JumpTarget::Label { label, .. } => format!("label_{label}"),
+    }
+}
+
+// Where a call or jmp goes
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum BranchTarget {
+    // Direct with an 8 bit displacement, only jmp has this form
+    Short(JumpTarget),
+    // Direct with a 16 bit displacement
+    Near(JumpTarget),
+    // Direct to an absolute segment:offset
+    Far { segment: u16, offset: u16 },
+    // Through a register or a word in memory
+    Indirect(Operand),
+    // Through an offset and segment pair in memory
+    IndirectFar(Operand),
+}
+
+impl BranchTarget {
+    fn render(&self, hex: bool) -> String {
+        match self {
+            BranchTarget::Short(target) => jump_target_str(target, 2, hex),
+            // Marked near so NASM doesn't shrink a jmp with a small displacement to the short form
+            BranchTarget::Near(target) => format!("near {}", jump_target_str(target, 3, hex)),
+            BranchTarget::Far { segment, offset } => {
+                format!(
+                    "{}:{}",
+                    number(*segment as i32, hex),
+                    number(*offset as i32, hex)
+                )
+            }
+            BranchTarget::Indirect(operand) => sized_str(operand, true, hex),
+            BranchTarget::IndirectFar(operand) => format!("far {}", operand.render(hex)),
+        }
+    }
+}
+
+impl Display for BranchTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(false))
+    }
+}
+
+// Port of an in or out, either an 8 bit constant or the port held in dx
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum Port {
+    Fixed(u8),
+    Dx,
+}
+
+impl Port {
+    fn render(&self, hex: bool) -> String {
+        match self {
+            Port::Fixed(port) => number(*port as i32, hex),
+            Port::Dx => "dx".to_string(),
+        }
+    }
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(false))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize)]
+pub enum Instruction {
+    Mov {
+        dst: Operand,
+        src: Operand,
+    },
+    Add {
+        dst: Operand,
+        src: Operand,
+    },
+    Sub {
+        dst: Operand,
+        src: Operand,
+    },
+    Cmp {
+        dst: Operand,
+        src: Operand,
+    },
+    Or {
+        dst: Operand,
+        src: Operand,
+    },
+    Adc {
+        dst: Operand,
+        src: Operand,
+    },
+    Sbb {
+        dst: Operand,
+        src: Operand,
+    },
+    And {
+        dst: Operand,
+        src: Operand,
+    },
+    Xor {
+        dst: Operand,
+        src: Operand,
+    },
+    Jump {
+        mnemonic: &'static str,
+        target: JumpTarget,
+    },
+    Call {
+        target: BranchTarget,
+    },
+    Jmp {
+        target: BranchTarget,
+    },
+    Push {
+        src: Operand,
+    },
+    Pop {
+        dst: Operand,
+    },
+    // A rep in front is in the Decoded prefixes
+    StringOp {
+        op: StringOperation,
+        word: bool,
+    },
+    Inc {
+        dst: Operand,
+        wide: bool,
+    },
+    Dec {
+        dst: Operand,
+        wide: bool,
+    },
+    Test {
+        dst: Operand,
+        src: Operand,
+    },
+    Not {
+        dst: Operand,
+        wide: bool,
+    },
+    Neg {
+        dst: Operand,
+        wide: bool,
+    },
+    Mul {
+        src: Operand,
+        wide: bool,
+    },
+    Imul {
+        src: Operand,
+        wide: bool,
+    },
+    Div {
+        src: Operand,
+        wide: bool,
+    },
+    Idiv {
+        src: Operand,
+        wide: bool,
+    },
+    Xchg {
+        dst: Operand,
+        src: Operand,
+    },
+    Lea {
+        dst: Operand,
+        src: Operand,
+    },
+    // lds and les, loading the offset of the far pointer at src into dst and its segment into
+    // segment
+    LoadPointer {
+        segment: SegmentRegister,
+        dst: Operand,
+        src: Operand,
+    },
+    In {
+        dst: Operand,
+        port: Port,
+    },
+    Out {
+        port: Port,
+        src: Operand,
+    },
+    // A byte that couldn't be decoded, emitted as data when skipping unknown instructions
+    Db {
+        byte: u8,
+    },
+    NoOperand {
+        mnemonic: &'static str,
+    },
+}
+
+impl Instruction {
+    // The operation without operands or prefixes, string operations without their size suffix
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Mov { .. } => "mov",
+            Instruction::Add { .. } => "add",
+            Instruction::Sub { .. } => "sub",
+            Instruction::Cmp { .. } => "cmp",
+            Instruction::Or { .. } => "or",
+            Instruction::Adc { .. } => "adc",
+            Instruction::Sbb { .. } => "sbb",
+            Instruction::And { .. } => "and",
+            Instruction::Xor { .. } => "xor",
+            Instruction::Jump { mnemonic, .. } => mnemonic,
+            Instruction::Call { .. } => "call",
+            Instruction::Jmp { .. } => "jmp",
+            Instruction::Push { .. } => "push",
+            Instruction::Pop { .. } => "pop",
+            Instruction::StringOp { op, .. } => op.into(),
+            Instruction::Inc { .. } => "inc",
+            Instruction::Dec { .. } => "dec",
+            Instruction::Test { .. } => "test",
+            Instruction::Not { .. } => "not",
+            Instruction::Neg { .. } => "neg",
+            Instruction::Mul { .. } => "mul",
+            Instruction::Imul { .. } => "imul",
+            Instruction::Div { .. } => "div",
+            Instruction::Idiv { .. } => "idiv",
+            Instruction::Xchg { .. } => "xchg",
+            Instruction::Lea { .. } => "lea",
+            Instruction::LoadPointer { segment, .. } => match segment {
+                SegmentRegister::DS => "lds",
+                _ => "les",
+            },
+            Instruction::In { .. } => "in",
+            Instruction::Out { .. } => "out",
+            Instruction::Db { .. } => "db",
+            Instruction::NoOperand { mnemonic } => mnemonic,
+        }
+    }
+}
+
+impl Instruction {
+    fn fmt_radix(&self, f: &mut std::fmt::Formatter<'_>, hex: bool) -> std::fmt::Result {
+        let r = |operand: &Operand| operand.render(hex);
+
+        match self {
+            Instruction::Mov { dst, src } => {
+                write!(f, "mov {}, {}", r(dst), r(src))
+            }
+            Instruction::Add { dst, src } => {
+                write!(f, "add {}, {}", r(dst), r(src))
+            }
+            Instruction::Sub { dst, src } => {
+                write!(f, "sub {}, {}", r(dst), r(src))
+            }
+            Instruction::Cmp { dst, src } => {
+                write!(f, "cmp {}, {}", r(dst), r(src))
+            }
+            Instruction::Or { dst, src } => {
+                write!(f, "or {}, {}", r(dst), r(src))
+            }
+            Instruction::Adc { dst, src } => {
+                write!(f, "adc {}, {}", r(dst), r(src))
+            }
+            Instruction::Sbb { dst, src } => {
+                write!(f, "sbb {}, {}", r(dst), r(src))
+            }
+            Instruction::And { dst, src } => {
+                write!(f, "and {}, {}", r(dst), r(src))
+            }
+            Instruction::Xor { dst, src } => {
+                write!(f, "xor {}, {}", r(dst), r(src))
+            }
+            Instruction::Jump { mnemonic, target } => {
+                write!(f, "{mnemonic} {}", jump_target_str(target, 2, hex))
+            }
+            Instruction::Call { target } => write!(f, "call {}", target.render(hex)),
+            Instruction::Jmp { target } => write!(f, "jmp {}", target.render(hex)),
+            Instruction::Push { src } => write!(f, "push {}", sized_str(src, true, hex)),
+            Instruction::Pop { dst } => write!(f, "pop {}", sized_str(dst, true, hex)),
+            Instruction::StringOp { op, word } => {
+                write!(f, "{}{}", op.as_ref(), if *word { "w" } else { "b" })
+            }
+            Instruction::Inc { dst, wide } => write!(f, "inc {}", sized_str(dst, *wide, hex)),
+            Instruction::Dec { dst, wide } => write!(f, "dec {}", sized_str(dst, *wide, hex)),
+            Instruction::Test { dst, src } => {
+                write!(f, "test {}, {}", r(dst), r(src))
+            }
+            Instruction::Not { dst, wide } => write!(f, "not {}", sized_str(dst, *wide, hex)),
+            Instruction::Neg { dst, wide } => write!(f, "neg {}", sized_str(dst, *wide, hex)),
+            Instruction::Mul { src, wide } => write!(f, "mul {}", sized_str(src, *wide, hex)),
+            Instruction::Imul { src, wide } => write!(f, "imul {}", sized_str(src, *wide, hex)),
+            Instruction::Div { src, wide } => write!(f, "div {}", sized_str(src, *wide, hex)),
+            Instruction::Idiv { src, wide } => write!(f, "idiv {}", sized_str(src, *wide, hex)),
+            Instruction::Xchg { dst, src } => {
+                write!(f, "xchg {}, {}", r(dst), r(src))
+            }
+            // The register gives the size, and only the address is used so no size is needed
+            Instruction::Lea { dst, src } => {
+                write!(f, "lea {}, {}", r(dst), r(src))
+            }
+            // Always a word register and a doubleword in memory, so no size either
+            Instruction::LoadPointer { dst, src, .. } => {
+                write!(f, "{} {}, {}", self.mnemonic(), r(dst), r(src))
+            }
+            Instruction::In { dst, port } => write!(f, "in {}, {}", r(dst), port.render(hex)),
+            Instruction::Out { port, src } => write!(f, "out {}, {}", port.render(hex), r(src)),
+            Instruction::Db { byte } => write!(f, "db 0x{byte:02x}"),
+            Instruction::NoOperand { mnemonic } => write!(f, "{mnemonic}"),
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_radix(f, false)
+    }
+}
+
+impl LowerHex for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_radix(f, true)
+    }
+}
+
+// Prefix bytes read ahead of an instruction's opcode, in any order and combination
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct Prefixes {
+    // Segment override applying to the memory operands
+    pub segment: Option<SegmentRegister>,
+    // Repeat applying to a string instruction
+    pub rep: Option<RepKind>,
+    pub lock: bool,
+}
+
+struct Input<'a> {
+    input: &'a [u8],
+    index: usize,
+    // Prefixes of the instruction being decoded
+    prefixes: Prefixes,
+    // Whether a memory operand took the segment override, which is lost otherwise
+    segment_used: bool,
+}
+
+impl<'a> Input<'a> {
+    fn new(input: &[u8]) -> Input<'_> {
+        Input {
+            input,
+            index: 0,
+            prefixes: Prefixes::default(),
+            segment_used: false,
+        }
+    }
+
+    // Segment override for a memory operand of the instruction being decoded
+    fn segment(&mut self) -> Option<SegmentRegister> {
+        self.segment_used = true;
+        self.prefixes.segment
+    }
+
+    fn next_byte(&mut self) -> Result<u8, InstructionError> {
+        let byte = *self
+            .input
+            .get(self.index)
+            .ok_or(InstructionError::UnexpectedEof)?;
+        self.index += 1;
+        Ok(byte)
+    }
+
+    fn next_word(&mut self) -> Result<u16, InstructionError> {
+        let lo = self.next_byte()? as u16;
+        let hi = self.next_byte()? as u16;
+        Ok((hi << 8) | lo)
+    }
+
+    // Reads a signed byte sign extended to a word, as used by 8-bit displacements
+    fn next_i8(&mut self) -> Result<i16, InstructionError> {
+        Ok(self.next_byte()? as i8 as i16)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.index == self.input.len()
+    }
+}
+
+fn parse_mem(
+    input: &mut Input,
+    w: u8,
+    instruction_byte_2: u8,
+) -> Result<Operand, InstructionError> {
+    let mode = instruction_byte_2 >> 6;
+    let mem = instruction_byte_2 & 0b111;
+
+    Ok(match mode {
+        0b00 => {
+            if mem == 0b110 {
+                Operand::MemDirect {
+                    address: input.next_word()?,
+                    segment: input.segment(),
+                }
+            } else {
+                Operand::Mem {
+                    formula: EffectiveAddressFormula::from_repr(mem)
+                        .ok_or_else(|| format!("Invalid formula: {mem:b}"))?,
+                    displacement: None,
+                    segment: input.segment(),
+                }
+            }
+        }
+        0b01 => Operand::Mem {
+            formula: EffectiveAddressFormula::from_repr(mem)
+                .ok_or_else(|| format!("Invalid formula: {mem:b}"))?,
+            displacement: Some(input.next_i8()?),
+            segment: input.segment(),
+        },
+        0b10 => Operand::Mem {
+            formula: EffectiveAddressFormula::from_repr(mem)
+                .ok_or_else(|| format!("Invalid formula: {mem:b}"))?,
+            displacement: Some(input.next_word()? as i16),
+            segment: input.segment(),
+        },
+        0b11 => {
+            let w_reg_2 = (w << 3) | mem;
+
+            Register::from_repr(w_reg_2)
+                .map(Operand::Register)
+                .ok_or_else(|| format!("Invalid reg: {w_reg_2:b}"))?
+        }
+        _ => Err("Invalid mode".to_string())?,
+    })
+}
+
+// The `mod reg r/m` byte, reg is kept raw since some opcodes use it as an opcode extension
+struct ModRegRm {
+    reg: u8,
+    rm: Operand,
+}
+
+fn decode_mod_reg_rm(input: &mut Input, w: u8) -> Result<ModRegRm, InstructionError> {
+    let instruction_byte_2 = input.next_byte()?;
+
+    Ok(ModRegRm {
+        reg: (instruction_byte_2 >> 3) & 0b111,
+        rm: parse_mem(input, w, instruction_byte_2)?,
+    })
+}
+
+// Decodes the shared `d w | mod reg r/m` form, returning the operands as (dst, src)
+fn parse_reg_and_mem(
+    input: &mut Input,
+    instruction_byte_1: u8,
+) -> Result<(Operand, Operand), InstructionError> {
+    let d = (instruction_byte_1 >> 1) & 0b1;
+    let w = instruction_byte_1 & 0b1;
+
+    let ModRegRm { reg, rm } = decode_mod_reg_rm(input, w)?;
+
+    let w_reg = (w << 3) | reg;
+
+    let reg = Register::from_repr(w_reg)
+        .map(Operand::Register)
+        .ok_or_else(|| format!("Invalid reg: {w_reg:b}"))?;
+
+    Ok(if d > 0 { (reg, rm) } else { (rm, reg) })
+}
+
+// Decodes the `w | data | data if w` immediate-to-accumulator form, returning (dst, src)
+fn parse_immediate_to_acc(
+    input: &mut Input,
+    instruction_byte_1: u8,
+) -> Result<(Operand, Operand), InstructionError> {
+    let w = instruction_byte_1 & 0b1;
+
+    Ok(if w > 0 {
+        (
+            Operand::Register(Register::AX),
+            Operand::Immediate {
+                value: input.next_word()?,
+                wide: true,
+                needs_size: false,
+                signed: true,
+            },
+        )
+    } else {
+        (
+            Operand::Register(Register::AL),
+            Operand::Immediate {
+                value: input.next_byte()? as u16,
+                wide: false,
+                needs_size: false,
+                signed: true,
+            },
+        )
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct Decoded {
+    pub offset: usize,
+    pub len: usize,
+    pub label: Option<usize>,
+    pub prefixes: Prefixes,
+    pub instruction: Instruction,
+}
+
+// Room the alternate format pads mnemonics to, so operands start in the same column
+const MNEMONIC_WIDTH: usize = 6;
+
+// The segment override is shown on the memory operand, lock and rep go first. {:#} pads the
+// mnemonic to MNEMONIC_WIDTH, string operations have no operands and are left as they are. {:x}
+// and {:#x} do the same with numbers in hex
+impl Decoded {
+    fn fmt_radix(&self, f: &mut std::fmt::Formatter<'_>, hex: bool) -> std::fmt::Result {
+        if self.prefixes.lock {
+            write!(f, "lock ")?;
+        }
+        if let Some(rep) = self.prefixes.rep {
+            write!(f, "{} ", rep.as_ref())?;
+        }
+
+        let instruction = if hex {
+            format!("{:x}", self.instruction)
+        } else {
+            self.instruction.to_string()
+        };
+        match instruction.split_once(' ') {
+            Some((mnemonic, operands))
+                if f.alternate() && mnemonic == self.instruction.mnemonic() =>
+            {
+                write!(f, "{mnemonic:MNEMONIC_WIDTH$} {operands}")
+            }
+            _ => write!(f, "{instruction}"),
+        }
+    }
+}
+
+impl Display for Decoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_radix(f, false)
+    }
+}
+
+impl LowerHex for Decoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_radix(f, true)
+    }
+}
+
+// The relative target of a conditional jump, loop or direct near call or jmp
+fn jump_target(instruction: &mut Instruction) -> Option<&mut JumpTarget> {
+    match instruction {
+        Instruction::Jump { target, .. }
+        | Instruction::Call {
+            target: BranchTarget::Short(target) | BranchTarget::Near(target),
+        }
+        | Instruction::Jmp {
+            target: BranchTarget::Short(target) | BranchTarget::Near(target),
+        } => Some(target),
+        _ => None,
+    }
+}
+
+// Where the relative jump in d lands, None when it isn't one or already has a label
+// ip is 16 bits, so within the first segment a target before 0 or past 0xffff wraps around
+fn relative_target(d: &mut Decoded) -> Option<isize> {
+    match jump_target(&mut d.instruction)? {
+        JumpTarget::Relative(displacement) => {
+            let next = (d.offset + d.len) as isize;
+            let target = next + *displacement as isize;
+            Some(if next <= 0x10000 {
+                target.rem_euclid(0x10000)
+            } else {
+                target
+            })
+        }
+        JumpTarget::Label { .. } => None,
+    }
+}
+
+// Gives every jump target that starts an instruction a label and points the jumps at it,
+// targets landing mid-instruction or outside the input are left relative
+fn assign_labels(decoded: &mut [Decoded]) {
+    let mut targets = decoded
+        .iter_mut()
+        .filter_map(relative_target)
+        .collect::<Vec<_>>();
+
+    targets.sort_unstable();
+    targets.dedup();
+
+    let mut next_label = 0;
+    for d in decoded.iter_mut() {
+        if targets.binary_search(&(d.offset as isize)).is_ok() {
+            d.label = Some(next_label);
+            next_label += 1;
+        }
+    }
+
+    for i in 0..decoded.len() {
+        let Some(target) = relative_target(&mut decoded[i]) else {
+            continue;
+        };
+
+        let label = decoded
+            .binary_search_by_key(&target, |d| d.offset as isize)
+            .ok()
+            .and_then(|index| decoded[index].label);
+
+        if let (Some(label), Some(jump)) = (label, jump_target(&mut decoded[i].instruction)) {
+            *jump = JumpTarget::Label {
+                label,
+                displacement: jump.displacement(),
+            };
+        }
+    }
+}
+
+// Failure within a single instruction, turned into a DecodeError once the start offset is known
+enum InstructionError {
+    Invalid(String),
+    UnexpectedEof,
+}
+
+impl From<String> for InstructionError {
+    fn from(reason: String) -> Self {
+        InstructionError::Invalid(reason)
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Invalid {
+        offset: usize,
+        byte: u8,
+        reason: String,
+    },
+    UnexpectedEof {
+        offset: usize,
+    },
+    // The decoded lengths skip over or decode twice the bytes from offset
+    LengthMismatch {
+        offset: usize,
+    },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Invalid {
+                offset,
+                byte,
+                reason,
+            } => write!(
+                f,
+                "Unable to decode instruction at offset {offset} (0x{byte:02x}): {reason}"
+            ),
+            DecodeError::UnexpectedEof { offset } => write!(
+                f,
+                "Unexpected end of input in instruction at offset {offset}"
+            ),
+            DecodeError::LengthMismatch { offset } => write!(
+                f,
+                "Decoded lengths don't add up to the input, first mismatch at offset {offset}"
+            ),
+        }
+    }
+}
+
+// The arithmetic and logic operation selected by bits 5..3 of the opcode, or by the reg field of
+// the immediate group
+fn arithmetic(op: u8, dst: Operand, src: Operand) -> Instruction {
+    match op {
+        0b000 => Instruction::Add { dst, src },
+        0b001 => Instruction::Or { dst, src },
+        0b010 => Instruction::Adc { dst, src },
+        0b011 => Instruction::Sbb { dst, src },
+        0b100 => Instruction::And { dst, src },
+        0b101 => Instruction::Sub { dst, src },
+        0b110 => Instruction::Xor { dst, src },
+        _ => Instruction::Cmp { dst, src },
+    }
+}
+
+fn decode_instruction(
+    input: &mut Input,
+    instruction_byte_1: u8,
+) -> Result<Instruction, InstructionError> {
+    let opcode = Opcode::parse(instruction_byte_1)?;
+
+    Ok(match opcode {
+        Opcode::MovRegToRegOrRegToMem => {
+            let (dst, src) = parse_reg_and_mem(input, instruction_byte_1)?;
+            Instruction::Mov { dst, src }
+        }
+        Opcode::MovImmediateToMem => {
+            let w = instruction_byte_1 & 0b1;
+
+            let mem = decode_mod_reg_rm(input, w)?.rm;
+
+            let data = Operand::Immediate {
+                value: if w > 0 {
+                    input.next_word()?
+                } else {
+                    input.next_byte()? as u16
+                },
+                wide: w > 0,
+                needs_size: true,
+                signed: true,
+            };
+
+            Instruction::Mov {
+                dst: mem,
+                src: data,
+            }
+        }
+        Opcode::MovImmediateToReg => {
+            let w = (instruction_byte_1 >> 3) & 0b1;
+            let reg = instruction_byte_1 & 0b111;
+            let w_reg = (w << 3) | reg;
+
+            let dst = Register::from_repr(w_reg)
+                .map(Operand::Register)
+                .ok_or_else(|| format!("Invalid reg: {w_reg:b}"))?;
+
+            let data = Operand::Immediate {
+                value: if w > 0 {
+                    input.next_word()?
+                } else {
+                    input.next_byte()? as u16
+                },
+                wide: w > 0,
+                needs_size: false,
+                signed: true,
+            };
+
+            Instruction::Mov { dst, src: data }
+        }
+        Opcode::MovMemToAcc => {
+            let w = instruction_byte_1 & 0b1;
+
+            let addr = Operand::MemDirect {
+                address: input.next_word()?,
+                segment: input.segment(),
+            };
+
+            Instruction::Mov {
+                dst: Operand::Register(if w > 0 { Register::AX } else { Register::AL }),
+                src: addr,
+            }
+        }
+        Opcode::MovAccToMem => {
+            let w = instruction_byte_1 & 0b1;
+
+            let addr = Operand::MemDirect {
+                address: input.next_word()?,
+                segment: input.segment(),
+            };
+
+            Instruction::Mov {
+                dst: addr,
+                src: Operand::Register(if w > 0 { Register::AX } else { Register::AL }),
+            }
+        }
+        Opcode::ImmediateToRegOrMem => {
+            let s = (instruction_byte_1 >> 1) & 0b1;
+            let w = instruction_byte_1 & 0b1;
+
+            let ModRegRm { reg, rm } = decode_mod_reg_rm(input, w)?;
+
+            // 0x81 carries a full word, 0x83 a byte sign extended to a word, 0x80 and 0x82 a byte
+            let data = Operand::Immediate {
+                value: match (s, w) {
+                    (0, 1) => input.next_word()?,
+                    (1, 1) => input.next_i8()? as u16,
+                    _ => input.next_byte()? as u16,
+                },
+                wide: w > 0,
+                needs_size: !matches!(rm, Operand::Register(_)),
+                // Only the sign extended forms show it as negative, as in add cx, -1
+                signed: s > 0,
+            };
+
+            arithmetic(reg, rm, data)
+        }
+        Opcode::ConditionalJump => Instruction::Jump {
+            mnemonic: CONDITIONAL_JUMP_MNEMONICS[(instruction_byte_1 & 0b1111) as usize],
+            target: JumpTarget::Relative(input.next_i8()?),
+        },
+        Opcode::Loop => Instruction::Jump {
+            mnemonic: LOOP_MNEMONICS[(instruction_byte_1 & 0b11) as usize],
+            target: JumpTarget::Relative(input.next_i8()?),
+        },
+        Opcode::PushReg | Opcode::PopReg => {
+            let w_reg = 0b1000 | (instruction_byte_1 & 0b111);
+
+            let reg = Register::from_repr(w_reg)
+                .map(Operand::Register)
+                .ok_or_else(|| format!("Invalid reg: {w_reg:b}"))?;
+
+            if instruction_byte_1 & 0b1000 > 0 {
+                Instruction::Pop { dst: reg }
+            } else {
+                Instruction::Push { src: reg }
+            }
+        }
+        Opcode::IncDecReg => {
+            let w_reg = 0b1000 | (instruction_byte_1 & 0b111);
+
+            let dst = Register::from_repr(w_reg)
+                .map(Operand::Register)
+                .ok_or_else(|| format!("Invalid reg: {w_reg:b}"))?;
+
+            if instruction_byte_1 & 0b1000 > 0 {
+                Instruction::Dec { dst, wide: true }
+            } else {
+                Instruction::Inc { dst, wide: true }
+            }
+        }
+        Opcode::PushPopSegmentReg => {
+            let sr = (instruction_byte_1 >> 3) & 0b11;
+
+            let reg = SegmentRegister::from_repr(sr)
+                .map(Operand::SegmentRegister)
+                .ok_or_else(|| format!("Invalid segment reg: {sr:b}"))?;
+
+            if instruction_byte_1 & 0b1 > 0 {
+                Instruction::Pop { dst: reg }
+            } else {
+                Instruction::Push { src: reg }
+            }
+        }
+        Opcode::GroupFeFf => {
+            let w = instruction_byte_1 & 0b1;
+
+            let ModRegRm { reg, rm } = decode_mod_reg_rm(input, w)?;
+
+            match (reg, w) {
+                (0b000, _) => Instruction::Inc {
+                    dst: rm,
+                    wide: w > 0,
+                },
+                (0b001, _) => Instruction::Dec {
+                    dst: rm,
+                    wide: w > 0,
+                },
+                (0b010, 1) => Instruction::Call {
+                    target: BranchTarget::Indirect(rm),
+                },
+                (0b011, 1) if !matches!(rm, Operand::Register(_)) => Instruction::Call {
+                    target: BranchTarget::IndirectFar(rm),
+                },
+                (0b100, 1) => Instruction::Jmp {
+                    target: BranchTarget::Indirect(rm),
+                },
+                (0b101, 1) if !matches!(rm, Operand::Register(_)) => Instruction::Jmp {
+                    target: BranchTarget::IndirectFar(rm),
+                },
+                (0b110, 1) => Instruction::Push { src: rm },
+                _ => Err(format!("Unimplemented 0b1111_111w group op: {reg:03b}"))?,
+            }
+        }
+        Opcode::GroupF6F7 => {
+            let w = instruction_byte_1 & 0b1;
+            let wide = w > 0;
+
+            let ModRegRm { reg, rm } = decode_mod_reg_rm(input, w)?;
+
+            match reg {
+                // test is the only one of the group with an immediate after the r/m operand
+                0b000 => Instruction::Test {
+                    dst: rm,
+                    src: Operand::Immediate {
+                        value: if wide {
+                            input.next_word()?
+                        } else {
+                            input.next_byte()? as u16
+                        },
+                        wide,
+                        needs_size: !matches!(rm, Operand::Register(_)),
+                        signed: true,
+                    },
+                },
+                0b010 => Instruction::Not { dst: rm, wide },
+                0b011 => Instruction::Neg { dst: rm, wide },
+                0b100 => Instruction::Mul { src: rm, wide },
+                0b101 => Instruction::Imul { src: rm, wide },
+                0b110 => Instruction::Div { src: rm, wide },
+                0b111 => Instruction::Idiv { src: rm, wide },
+                _ => Err(format!("Invalid 0b1111_011w group op: {reg:03b}"))?,
+            }
+        }
+        Opcode::PopRegOrMem => {
+            let ModRegRm { reg, rm } = decode_mod_reg_rm(input, 1)?;
+
+            match reg {
+                0b000 => Instruction::Pop { dst: rm },
+                _ => Err(format!("Invalid pop op: {reg:03b}"))?,
+            }
+        }
+        Opcode::StringOp => Instruction::StringOp {
+            op: match instruction_byte_1 & 0b1111_1110 {
+                0b1010_0100 => StringOperation::Movs,
+                0b1010_0110 => StringOperation::Cmps,
+                0b1010_1010 => StringOperation::Stos,
+                0b1010_1100 => StringOperation::Lods,
+                _ => StringOperation::Scas,
+            },
+            word: instruction_byte_1 & 0b1 > 0,
+        },
+        Opcode::MovSegmentReg => {
+            let d = (instruction_byte_1 >> 1) & 0b1;
+
+            let ModRegRm { reg, rm } = decode_mod_reg_rm(input, 1)?;
+
+            let sr = SegmentRegister::from_repr(reg)
+                .map(Operand::SegmentRegister)
+                .ok_or_else(|| format!("Invalid segment reg: {reg:03b}"))?;
+
+            if d > 0 {
+                Instruction::Mov { dst: sr, src: rm }
+            } else {
+                Instruction::Mov { dst: rm, src: sr }
+            }
+        }
+        Opcode::XchgRegOrMem => {
+            let (dst, src) = parse_reg_and_mem(input, instruction_byte_1)?;
+            Instruction::Xchg { dst, src }
+        }
+        Opcode::XchgRegWithAcc => {
+            let w_reg = 0b1000 | (instruction_byte_1 & 0b111);
+
+            let src = Register::from_repr(w_reg)
+                .map(Operand::Register)
+                .ok_or_else(|| format!("Invalid reg: {w_reg:b}"))?;
+
+            Instruction::Xchg {
+                dst: Operand::Register(Register::AX),
+                src,
+            }
+        }
+        // Encoded with d = 0 even though the register is the destination
+        Opcode::Lea => {
+            let (src, dst) = parse_reg_and_mem(input, instruction_byte_1)?;
+
+            if matches!(src, Operand::Register(_)) {
+                Err("lea needs a memory operand".to_string())?;
+            }
+
+            Instruction::Lea { dst, src }
+        }
+        // Neither d nor w is encoded, the register is always a word and the destination
+        Opcode::LoadPointer => {
+            let (src, dst) = parse_reg_and_mem(input, 0b01)?;
+
+            let segment = if instruction_byte_1 & 0b1 > 0 {
+                SegmentRegister::DS
+            } else {
+                SegmentRegister::ES
+            };
+            let instruction = Instruction::LoadPointer { segment, dst, src };
+
+            if matches!(src, Operand::Register(_)) {
+                Err(format!("{} needs a memory operand", instruction.mnemonic()))?;
+            }
+
+            instruction
+        }
+        Opcode::InOutFixedPort | Opcode::InOutVariablePort => {
+            let acc = Operand::Register(if instruction_byte_1 & 0b1 > 0 {
+                Register::AX
+            } else {
+                Register::AL
+            });
+
+            let port = if matches!(opcode, Opcode::InOutFixedPort) {
+                Port::Fixed(input.next_byte()?)
+            } else {
+                Port::Dx
+            };
+
+            if instruction_byte_1 & 0b10 > 0 {
+                Instruction::Out { port, src: acc }
+            } else {
+                Instruction::In { dst: acc, port }
+            }
+        }
+        Opcode::ArithmeticRegToRegOrRegToMem => {
+            let (dst, src) = parse_reg_and_mem(input, instruction_byte_1)?;
+            arithmetic((instruction_byte_1 >> 3) & 0b111, dst, src)
+        }
+        Opcode::ArithmeticImmediateToAcc => {
+            let (dst, src) = parse_immediate_to_acc(input, instruction_byte_1)?;
+            arithmetic((instruction_byte_1 >> 3) & 0b111, dst, src)
+        }
+        Opcode::TestRegToRegOrRegToMem => {
+            let (dst, src) = parse_reg_and_mem(input, instruction_byte_1)?;
+            Instruction::Test { dst, src }
+        }
+        Opcode::TestImmediateToAcc => {
+            let (dst, src) = parse_immediate_to_acc(input, instruction_byte_1)?;
+            Instruction::Test { dst, src }
+        }
+        Opcode::NoOperand => Instruction::NoOperand {
+            mnemonic: no_operand_mnemonic(instruction_byte_1).unwrap(),
+        },
+        Opcode::CallNear => Instruction::Call {
+            target: BranchTarget::Near(JumpTarget::Relative(input.next_word()? as i16)),
+        },
+        Opcode::JmpNear => Instruction::Jmp {
+            target: BranchTarget::Near(JumpTarget::Relative(input.next_word()? as i16)),
+        },
+        Opcode::JmpShort => Instruction::Jmp {
+            target: BranchTarget::Short(JumpTarget::Relative(input.next_i8()?)),
+        },
+        // The offset comes before the segment
+        Opcode::CallFar | Opcode::JmpFar => {
+            let offset = input.next_word()?;
+            let segment = input.next_word()?;
+            let target = BranchTarget::Far { segment, offset };
+
+            if matches!(opcode, Opcode::CallFar) {
+                Instruction::Call { target }
+            } else {
+                Instruction::Jmp { target }
+            }
+        }
+    })
+}
+
+fn segment_override(byte: u8) -> Option<SegmentRegister> {
+    if byte & 0b1110_0111 == 0b0010_0110 {
+        SegmentRegister::from_repr((byte >> 3) & 0b11)
+    } else {
+        None
+    }
+}
+
+// The decoded opcode for every possible first byte, None where it is not implemented
+fn opcode_coverage() -> [Option<Opcode>; 256] {
+    let mut res = [None; 256];
+    for (byte, opcode) in res.iter_mut().enumerate() {
+        *opcode = Opcode::parse(byte as u8).ok();
+    }
+    res
+}
+
+pub fn print_coverage(w: &mut dyn Write) {
+    let coverage = opcode_coverage();
+
+    writeln!(w, "     0 1 2 3 4 5 6 7 8 9 a b c d e f").unwrap();
+    for (hi, row) in coverage.chunks(16).enumerate() {
+        write!(w, "{hi:x}_  ").unwrap();
+        for (lo, opcode) in row.iter().enumerate() {
+            let byte = (hi * 16 + lo) as u8;
+            let mark = if opcode.is_some() {
+                "x"
+            } else if segment_override(byte).is_some()
+                || rep_prefix(byte).is_some()
+                || byte == LOCK_PREFIX
+            {
+                "p"
+            } else {
+                "."
+            };
+            write!(w, " {mark}").unwrap();
+        }
+        writeln!(w).unwrap();
+    }
+    writeln!(w, "x = opcode, p = prefix, . = not implemented").unwrap();
+
+    writeln!(
+        w,
+        "Implemented: {}/256",
+        coverage.iter().filter(|opcode| opcode.is_some()).count()
+    )
+    .unwrap();
+}
+
+const LOCK_PREFIX: u8 = 0b1111_0000;
+
+fn rep_prefix(byte: u8) -> Option<RepKind> {
+    match byte {
+        0b1111_0011 => Some(RepKind::Rep),
+        0b1111_0010 => Some(RepKind::Repne),
+        _ => None,
+    }
+}
+
+pub fn decode(input: &[u8]) -> Result<Vec<Decoded>, DecodeError> {
+    decode_files(input, &[input.len()], false, None)
+}
+
+// The assembly pap86 prints without any options, for callers that only want the text like the
+// wasm playground
+pub fn decode_to_string(input: &[u8]) -> Result<String, DecodeError> {
+    let mut res = "bits 16\n".to_string();
+
+    for decoded in decode(input)? {
+        if let Some(label) = decoded.label {
+            res += &format!("label_{label}:\n");
+        }
+        res += &format!("{decoded}\n");
+    }
+
+    Ok(res)
+}
+
+// Whitespace or commas between bytes, runs of bytes without separators and 0x prefixes are all
+// accepted
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+
+    for token in text.split(|c: char| c.is_whitespace() || c == ',') {
+        let digits = token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+            .unwrap_or(token);
+
+        if digits.is_empty() && digits.len() != token.len() {
+            return Err(format!("No hex digits after {token}"));
+        }
+
+        if digits.len() % 2 != 0 {
+            return Err(format!("Odd number of hex digits in {token}"));
+        }
+
+        for i in (0..digits.len()).step_by(2) {
+            let byte = digits
+                .get(i..i + 2)
+                .filter(|pair| pair.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("Invalid hex byte in {token}"))?;
+            bytes.push(byte);
+        }
+    }
+
+    Ok(bytes)
+}
+
+// Exports for a browser playground, built with
+// cargo rustc -p pap86 --lib --crate-type cdylib --target wasm32-unknown-unknown --release
+// They use the plain C ABI so the page needs no wasm-bindgen glue: it allocates room for the
+// bytes with pap86_alloc, copies them in, calls pap86_decode and reads the text back from the
+// returned pointer and length before freeing both with pap86_free
+#[cfg(target_arch = "wasm32")]
+pub mod wasm {
+    use std::mem;
+
+    #[no_mangle]
+    pub extern "C" fn pap86_alloc(len: usize) -> *mut u8 {
+        let mut buffer = Vec::<u8>::with_capacity(len);
+        let ptr = buffer.as_mut_ptr();
+        mem::forget(buffer);
+        ptr
+    }
+
+    /// # Safety
+    /// ptr and len must come from pap86_alloc or pap86_decode and not have been freed already
+    #[no_mangle]
+    pub unsafe extern "C" fn pap86_free(ptr: *mut u8, len: usize) {
+        drop(Vec::from_raw_parts(ptr, 0, len));
+    }
+
+    /// Decodes len bytes at ptr and returns the assembly, or the error as a comment, with its
+    /// length written to out_len. The text has to be freed with that length
+    ///
+    /// # Safety
+    /// ptr must point to len readable bytes and out_len to a writable usize
+    #[no_mangle]
+    pub unsafe extern "C" fn pap86_decode(
+        ptr: *const u8,
+        len: usize,
+        out_len: *mut usize,
+    ) -> *mut u8 {
+        let input = std::slice::from_raw_parts(ptr, len);
+        let text = super::decode_to_string(input).unwrap_or_else(|e| format!("; {e}\n"));
+
+        let mut text = mem::ManuallyDrop::new(text.into_bytes().into_boxed_slice());
+        *out_len = text.len();
+        text.as_mut_ptr()
+    }
+}
+
+// Decodes several files laid end to end in input, with ends holding the offset each one stops at.
+// Decoding starts over at every file so no instruction runs on into the next one. With
+// skip_unknown a byte that doesn't start a valid instruction becomes a db and decoding resumes
+// at the next byte. Decoding stops early once there are limit instructions
+pub fn decode_files(
+    input: &[u8],
+    ends: &[usize],
+    skip_unknown: bool,
+    limit: Option<usize>,
+) -> Result<Vec<Decoded>, DecodeError> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut res = Vec::new();
+    let mut start = 0;
+
+    for &end in ends {
+        let mut file = DecodeIter::new(&input[..end], skip_unknown);
+        file.input.index = start;
+        start = end;
+
+        for decoded in file.take(limit - res.len()) {
+            res.push(decoded?);
+        }
+    }
+
+    // An instruction consuming the wrong number of bytes would shift everything after it, or
+    // ends not matching input would drop or repeat bytes
+    if res.len() < limit {
+        check_lengths(&res, input.len())?;
+    }
+
+    assign_labels(&mut res);
+
+    Ok(res)
+}
+
+// Decodes one instruction at a time, so a caller that doesn't need every instruction at once
+// never holds them all. Labels are left unassigned since a jump can target any instruction,
+// decode collects the instructions to assign them
+pub fn decode_iter(input: &[u8]) -> DecodeIter<'_> {
+    DecodeIter::new(input, false)
+}
+
+pub struct DecodeIter<'a> {
+    input: Input<'a>,
+    skip_unknown: bool,
+}
+
+impl DecodeIter<'_> {
+    fn new(input: &[u8], skip_unknown: bool) -> DecodeIter<'_> {
+        DecodeIter {
+            input: Input::new(input),
+            skip_unknown,
+        }
+    }
+}
+
+// Iteration ends after the first error unless unknown bytes are skipped
+impl Iterator for DecodeIter<'_> {
+    type Item = Result<Decoded, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.is_empty() {
+            return None;
+        }
+
+        let e = match decode_next(&mut self.input) {
+            Ok(decoded) => return Some(Ok(decoded)),
+            Err(e) => e,
+        };
+
+        if !self.skip_unknown {
+            self.input.index = self.input.input.len();
+            return Some(Err(e));
+        }
+
+        let offset = match e {
+            DecodeError::Invalid { offset, .. }
+            | DecodeError::UnexpectedEof { offset }
+            | DecodeError::LengthMismatch { offset } => offset,
+        };
+        self.input.index = offset + 1;
+
+        Some(Ok(Decoded {
+            offset,
+            len: 1,
+            label: None,
+            prefixes: Prefixes::default(),
+            instruction: Instruction::Db {
+                byte: self.input.input[offset],
+            },
+        }))
+    }
+}
+
+// Checks that every instruction starts where the previous one ended and the last one ends at the
+// end of the input, catching an instruction that consumed the wrong number of bytes
+pub fn check_lengths(instructions: &[Decoded], input_len: usize) -> Result<(), DecodeError> {
+    let mut end = 0;
+
+    for decoded in instructions {
+        if decoded.offset != end {
+            return Err(DecodeError::LengthMismatch { offset: end });
+        }
+        end += decoded.len;
+    }
+
+    if end != input_len {
+        return Err(DecodeError::LengthMismatch { offset: end });
+    }
+
+    Ok(())
+}
+
+// Reads any prefix bytes, leaving input at the opcode
+fn read_prefixes(input: &mut Input) -> Prefixes {
+    let mut prefixes = Prefixes::default();
+
+    while let Some(&byte) = input.input.get(input.index) {
+        if let Some(segment) = segment_override(byte) {
+            prefixes.segment = Some(segment);
+        } else if let Some(rep) = rep_prefix(byte) {
+            prefixes.rep = Some(rep);
+        } else if byte == LOCK_PREFIX {
+            prefixes.lock = true;
+        } else {
+            break;
+        }
+
+        input.index += 1;
+    }
+
+    prefixes
+}
+
+fn decode_next(input: &mut Input) -> Result<Decoded, DecodeError> {
+    let offset = input.index;
+
+    input.prefixes = read_prefixes(input);
+    input.segment_used = false;
+
+    let instruction_byte_1 = input
+        .next_byte()
+        .map_err(|_| DecodeError::UnexpectedEof { offset })?;
+
+    let instruction = decode_instruction(input, instruction_byte_1).map_err(|e| match e {
+        InstructionError::Invalid(reason) => DecodeError::Invalid {
+            offset,
+            byte: instruction_byte_1,
+            reason,
+        },
+        InstructionError::UnexpectedEof => DecodeError::UnexpectedEof { offset },
+    })?;
+
+    // Prefixes that the instruction has no use for would be dropped from the output
+    let unused_prefix =
+        if input.prefixes.rep.is_some() && !matches!(instruction, Instruction::StringOp { .. }) {
+            Some("rep prefix on an instruction that isn't a string operation")
+        } else if input.prefixes.segment.is_some() && !input.segment_used {
+            Some("segment prefix on an instruction without a memory operand")
+        } else {
+            None
+        };
+    if let Some(reason) = unused_prefix {
+        return Err(DecodeError::Invalid {
+            offset,
+            byte: instruction_byte_1,
+            reason: reason.to_string(),
+        });
+    }
+
+    Ok(Decoded {
+        offset,
+        len: input.index - offset,
+        label: None,
+        prefixes: input.prefixes,
+        instruction,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disassemble(bytes: &[u8]) -> Vec<String> {
+        decode(bytes)
+            .unwrap()
+            .iter()
+            .map(|decoded| decoded.to_string())
+            .collect()
+    }
+
+    fn decode_error(bytes: &[u8]) -> String {
+        decode(bytes).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn listing_lengths_add_up_to_the_file() {
+        let input = include_bytes!("../../perfaware/part1/listing_0040_challenge_movs");
+        let decoded = decode(input).unwrap();
+
+        assert_eq!(decoded.iter().map(|d| d.len).sum::<usize>(), input.len());
+        assert!(check_lengths(&decoded, input.len()).is_ok());
+    }
+
+    #[test]
+    fn truncated_instruction_is_an_error() {
+        assert!(matches!(
+            decode(&[0b1011_1000]),
+            Err(DecodeError::UnexpectedEof { offset: 0 })
+        ));
+        // mov ax, bx then mov ax, imm16 with one of its data bytes
+        assert!(matches!(
+            decode(&[0x89, 0xd8, 0xb8, 0x01]),
+            Err(DecodeError::UnexpectedEof { offset: 2 })
+        ));
+    }
+
+    #[test]
+    fn files_are_decoded_separately() {
+        // mov ax, 1 split after its opcode byte runs into the end of the first file
+        let input = [0xb8, 0x01, 0x00];
+        assert!(decode_files(&input, &[1, 3], false, None).is_err());
+        assert_eq!(decode_files(&input, &[3], false, None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn decode_iter_matches_decode() {
+        // mov cx, 3, dec cx, jnz back, es: mov ax, [bx], rep movsb, hlt
+        let input = [
+            0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd, 0x26, 0x8b, 0x07, 0xf3, 0xa4, 0xf4,
+        ];
+        let decoded = decode(&input).unwrap();
+        let iterated = decode_iter(&input).collect::<Result<Vec<_>, _>>().unwrap();
+
+        // The same instructions, only without labels
+        assert_eq!(iterated.len(), decoded.len());
+        for (i, d) in iterated.iter().zip(&decoded) {
+            assert_eq!((i.offset, i.len), (d.offset, d.len));
+            assert_eq!(i.label, None);
+        }
+        assert_eq!(
+            iterated.iter().map(|d| d.to_string()).collect::<Vec<_>>(),
+            [
+                "mov cx, 3",
+                "dec cx",
+                "jne $-1",
+                "mov ax, es:[bx]",
+                "rep movsb",
+                "hlt"
+            ]
+        );
+        assert_eq!(decoded[2].to_string(), "jne label_0");
+    }
+
+    #[test]
+    fn decode_iter_stops_at_the_first_error() {
+        // mov cx, 3, an invalid 0x0f, then a hlt that is never reached
+        let input = [0xb9, 0x03, 0x00, 0x0f, 0xf4];
+        let mut iter = decode_iter(&input);
+
+        assert_eq!(iter.next().unwrap().unwrap().to_string(), "mov cx, 3");
+        let error = iter.next().unwrap().unwrap_err();
+        assert!(matches!(error, DecodeError::Invalid { offset: 3, .. }));
+        assert!(iter.next().is_none());
+
+        // decode fails with the same error
+        assert_eq!(decode(&input).unwrap_err().to_string(), error.to_string());
+    }
+
+    #[test]
+    fn decoded_to_string() {
+        // mov cx, 3, label, dec cx, jnz back to it
+        assert_eq!(
+            decode_to_string(&[0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd]).unwrap(),
+            "bits 16\nmov cx, 3\nlabel_0:\ndec cx\njne label_0\n"
+        );
+        assert!(matches!(
+            decode_to_string(&[0xb9, 0x03]),
+            Err(DecodeError::UnexpectedEof { offset: 0 })
+        ));
+        assert!(matches!(
+            decode_to_string(&[0x0f]),
+            Err(DecodeError::Invalid { byte: 0x0f, .. })
+        ));
+    }
+
+    #[test]
+    fn hex_text() {
+        let bytes = [0x89, 0xd9, 0x88, 0xcd];
+        assert_eq!(parse_hex("89 d9 88 cd").unwrap(), bytes);
+        assert_eq!(parse_hex(" 89,d9,\n88\tCD\n").unwrap(), bytes);
+        assert_eq!(parse_hex("89d9 88cd").unwrap(), bytes);
+        assert_eq!(parse_hex("0x89, 0XD9, 0x88cd").unwrap(), bytes);
+        assert_eq!(parse_hex("").unwrap(), []);
+    }
+
+    #[test]
+    fn invalid_hex_text() {
+        let error = |text| parse_hex(text).unwrap_err();
+        assert_eq!(error("89 d"), "Odd number of hex digits in d");
+        assert_eq!(error("0x8"), "Odd number of hex digits in 0x8");
+        assert_eq!(error("89 zz"), "Invalid hex byte in zz");
+        assert_eq!(error("8g"), "Invalid hex byte in 8g");
+        // A sign would get past from_str_radix on its own
+        assert_eq!(error("+f"), "Invalid hex byte in +f");
+        assert_eq!(error("89;d"), "Invalid hex byte in 89;d");
+        // Two bytes of UTF-8 rather than two digits
+        assert_eq!(error("\u{e9}12"), "Invalid hex byte in \u{e9}12");
+        assert_eq!(error("0x"), "No hex digits after 0x");
+        assert_eq!(error("00xff"), "Odd number of hex digits in 00xff");
+    }
+
+    #[test]
+    fn used_prefixes() {
+        assert_eq!(
+            disassemble(&[0x26, 0x8b, 0x07, 0xf3, 0xa4]),
+            ["mov ax, es:[bx]", "rep movsb"]
+        );
+    }
+
+    #[test]
+    fn rep_is_only_in_the_prefixes() {
+        // lock repne scasw, the instruction alone is the string operation
+        let decoded = decode(&[0xf0, 0xf2, 0xaf]).unwrap();
+        assert!(matches!(decoded[0].prefixes.rep, Some(RepKind::Repne)));
+        assert_eq!(decoded[0].instruction.to_string(), "scasw");
+        assert_eq!(decoded[0].to_string(), "lock repne scasw");
+        assert_eq!(format!("{:#}", decoded[0]), "lock repne scasw");
+    }
+
+    #[test]
+    fn unused_prefixes_are_rejected() {
+        // rep mov ax, bx
+        assert!(decode_error(&[0xf3, 0x89, 0xd8]).contains("rep prefix"));
+        // es: mov ax, bx
+        assert!(decode_error(&[0x26, 0x89, 0xd8]).contains("segment prefix"));
+    }
+
+    #[test]
+    fn accumulator_short_and_long_direct_forms() {
+        // Each accumulator move to or from [16] in its short form, then in its ModRM form
+        let input = [
+            0xa1, 0x10, 0x00, 0x8b, 0x06, 0x10, 0x00, 0xa3, 0x10, 0x00, 0x89, 0x06, 0x10, 0x00,
+            0xa0, 0x10, 0x00, 0x8a, 0x06, 0x10, 0x00, 0xa2, 0x10, 0x00, 0x88, 0x06, 0x10, 0x00,
+        ];
+        let decoded = decode(&input).unwrap();
+
+        assert_eq!(
+            disassemble(&input),
+            [
+                "mov ax, [16]",
+                "mov ax, [16]",
+                "mov [16], ax",
+                "mov [16], ax",
+                "mov al, [16]",
+                "mov al, [16]",
+                "mov [16], al",
+                "mov [16], al",
+            ]
+        );
+        assert_eq!(
+            decoded.iter().map(|d| d.len).collect::<Vec<_>>(),
+            [3, 4, 3, 4, 3, 4, 3, 4]
+        );
+
+        // Both forms encode to the short one, which decodes back to the same instructions
+        let instructions = decoded.iter().map(|d| d.instruction).collect::<Vec<_>>();
+        let encoded = encode::encode(&instructions).unwrap();
+        let short = [0xa1, 0xa3, 0xa0, 0xa2]
+            .iter()
+            .flat_map(|&opcode| [opcode, 0x10, 0x00].repeat(2))
+            .collect::<Vec<_>>();
+        assert_eq!(encoded, short);
+        assert_eq!(disassemble(&encoded), disassemble(&input));
+    }
+
+    #[test]
+    fn immediate_to_memory_size_comes_from_the_w_bit() {
+        // {byte, word} x {fits in a byte, doesn't} to [bx] and to [16]
+        let input = [
+            0xc6, 0x07, 0x05, 0xc6, 0x07, 0xc8, 0xc7, 0x07, 0xc8, 0x00, 0xc7, 0x07, 0x2c, 0x01,
+            0xc6, 0x06, 0x10, 0x00, 0x05, 0xc6, 0x06, 0x10, 0x00, 0xc8, 0xc7, 0x06, 0x10, 0x00,
+            0xc8, 0x00, 0xc7, 0x06, 0x10, 0x00, 0x2c, 0x01,
+        ];
+
+        assert_eq!(
+            disassemble(&input),
+            [
+                "mov [bx], byte 5",
+                "mov [bx], byte -56",
+                "mov [bx], word 200",
+                "mov [bx], word 300",
+                "mov [16], byte 5",
+                "mov [16], byte -56",
+                "mov [16], word 200",
+                "mov [16], word 300",
+            ]
+        );
+
+        let instructions = decode(&input)
+            .unwrap()
+            .iter()
+            .map(|d| d.instruction)
+            .collect::<Vec<_>>();
+        assert_eq!(encode::encode(&instructions).unwrap(), input);
+    }
+
+    #[test]
+    fn labels_keep_the_displacement() {
+        // mov cx, 3, dec cx, jne back to the dec
+        let decoded = decode(&[0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd]).unwrap();
+
+        assert_eq!(decoded[1].label, Some(0));
+        assert_eq!(decoded[2].to_string(), "jne label_0");
+        match decoded[2].instruction {
+            Instruction::Jump { target, .. } => assert_eq!(target.displacement(), -3),
+            _ => panic!("expected a jump"),
+        }
+    }
+
+    #[test]
+    fn word_store_of_a_small_value() {
+        // mov word [bp], 1 has to encode [bp] with a zero displacement
+        let input = [0xc7, 0x46, 0x00, 0x01, 0x00];
+        assert_eq!(disassemble(&input), ["mov [bp], word 1"]);
+
+        let decoded = decode(&input).unwrap();
+        assert_eq!(encode::encode(&[decoded[0].instruction]).unwrap(), input);
+    }
+
+    #[test]
+    fn unary_group() {
+        assert_eq!(
+            disassemble(&[
+                0xf6, 0xd8, 0xf7, 0xd0, 0xf6, 0xe3, 0xf7, 0xef, 0xf6, 0x36, 0x10, 0x00, 0xf7, 0x7f,
+                0x04, 0xf7, 0xe1, 0xf6, 0xf9,
+            ]),
+            [
+                "neg al",
+                "not ax",
+                "mul bl",
+                "imul di",
+                "div byte [16]",
+                "idiv word [bx + 4]",
+                "mul cx",
+                "idiv cl",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reads_an_immediate_after_the_modrm() {
+        // test bl, 5, test word [bx], 256, test byte [bx + 2], -128, then a neg to check the
+        // immediates were consumed
+        let input = [
+            0xf6, 0xc3, 0x05, 0xf7, 0x07, 0x00, 0x01, 0xf6, 0x47, 0x02, 0x80, 0xf7, 0xd8,
+        ];
+
+        assert_eq!(
+            disassemble(&input),
+            [
+                "test bl, 5",
+                "test [bx], word 256",
+                "test [bx + 2], byte -128",
+                "neg ax",
+            ]
+        );
+        assert_eq!(
+            decode(&input)
+                .unwrap()
+                .iter()
+                .map(|d| d.len)
+                .collect::<Vec<_>>(),
+            [3, 4, 4, 2]
+        );
+    }
+
+    #[test]
+    fn xchg_in_out_and_lea() {
+        let input = [
+            0x86, 0xc3, 0x87, 0x0f, 0x91, 0x90, 0x8d, 0x5e, 0x04, 0x8d, 0x87, 0x00, 0x01, 0xe4,
+            0x60, 0xe5, 0x60, 0xec, 0xed, 0xe6, 0x61, 0xe7, 0x61, 0xee, 0xef,
+        ];
+
+        assert_eq!(
+            disassemble(&input),
+            [
+                "xchg al, bl",
+                "xchg cx, [bx]",
+                "xchg ax, cx",
+                "nop",
+                "lea bx, [bp + 4]",
+                "lea ax, [bx + 256]",
+                "in al, 96",
+                "in ax, 96",
+                "in al, dx",
+                "in ax, dx",
+                "out 97, al",
+                "out 97, ax",
+                "out dx, al",
+                "out dx, ax",
+            ]
+        );
+
+        assert_eq!(
+            decode(&input)
+                .unwrap()
+                .iter()
+                .map(|d| d.len)
+                .collect::<Vec<_>>(),
+            [2, 2, 1, 1, 3, 4, 2, 2, 1, 1, 2, 2, 1, 1]
+        );
+    }
+
+    #[test]
+    fn lea_needs_a_memory_operand() {
+        assert!(decode_error(&[0x8d, 0xc3]).contains("lea needs a memory operand"));
+    }
+
+    #[test]
+    fn mov_immediate_to_register_width() {
+        // mov al, 5, mov ax, 5, mov ah, -1, mov di, 4660
+        let input = [0xb0, 0x05, 0xb8, 0x05, 0x00, 0xb4, 0xff, 0xbf, 0x34, 0x12];
+        let decoded = decode(&input).unwrap();
+
+        assert_eq!(
+            disassemble(&input),
+            ["mov al, 5", "mov ax, 5", "mov ah, -1", "mov di, 4660"]
+        );
+        assert_eq!(
+            decoded.iter().map(|d| d.len).collect::<Vec<_>>(),
+            [2, 3, 2, 3]
+        );
+
+        let instructions = decoded.iter().map(|d| d.instruction).collect::<Vec<_>>();
+        assert_eq!(encode::encode(&instructions).unwrap(), input);
+    }
+
+    #[test]
+    fn hex_numbers() {
+        let input = [
+            0xc7, 0x87, 0x2c, 0x01, 0xe8, 0x03, 0x83, 0xc1, 0xff, 0xea, 0x10, 0x00, 0x34, 0x12,
+            0xe4, 0x60, 0x8b, 0x46, 0xfe,
+        ];
+        let decoded = decode(&input).unwrap();
+
+        // The radix is chosen per format, so both render from the same decode
+        assert_eq!(
+            decoded.iter().map(|d| format!("{d:x}")).collect::<Vec<_>>(),
+            [
+                "mov [bx + 0x12c], word 0x3e8",
+                "add cx, -0x1",
+                "jmp 0x1234:0x10",
+                "in al, 0x60",
+                "mov ax, [bp - 0x2]",
+            ]
+        );
+        assert_eq!(
+            disassemble(&input),
+            [
+                "mov [bx + 300], word 1000",
+                "add cx, -1",
+                "jmp 4660:16",
+                "in al, 96",
+                "mov ax, [bp - 2]",
+            ]
+        );
+        assert_eq!(format!("{:#x}", decoded[1]), "add    cx, -0x1");
+    }
+
+    #[test]
+    fn xor_of_a_register_with_itself() {
+        // Both directions of the reg field decode the same, NASM emits d = 0
+        assert_eq!(
+            disassemble(&[0x31, 0xc9, 0x33, 0xc9]),
+            ["xor cx, cx", "xor cx, cx"]
+        );
+
+        let decoded = decode(&[0x33, 0xc9]).unwrap();
+        assert_eq!(
+            encode::encode(&[decoded[0].instruction]).unwrap(),
+            [0x31, 0xc9]
+        );
+        let decoded = decode(&[0x31, 0xc9]).unwrap();
+        assert_eq!(
+            encode::encode(&[decoded[0].instruction]).unwrap(),
+            [0x31, 0xc9]
+        );
+    }
+
+    #[test]
+    fn word_immediates_to_memory() {
+        // 1000 needs the full word of 0x81, -5 fits the sign extended byte of 0x83
+        let input = [0x81, 0x07, 0xe8, 0x03, 0x83, 0x07, 0xfb];
+        let decoded = decode(&input).unwrap();
+
+        assert_eq!(
+            disassemble(&input),
+            ["add [bx], word 1000", "add [bx], word -5"]
+        );
+        assert_eq!(decoded.iter().map(|d| d.len).collect::<Vec<_>>(), [4, 3]);
+        match decoded[1].instruction {
+            Instruction::Add {
+                src: Operand::Immediate { value, wide, .. },
+                ..
+            } => assert_eq!((value, wide), (-5i16 as u16, true)),
+            _ => panic!("expected an add immediate"),
+        }
+    }
+
+    #[test]
+    fn sign_extended_immediates_print_signed() {
+        // -1 as a sign extended byte after 0x83 and as a full word after 0x81
+        let input = [
+            0x83, 0xc1, 0xff, 0x81, 0xc1, 0xff, 0xff, 0x83, 0xe8, 0xff, 0x81, 0xe8, 0xff, 0xff,
+        ];
+        let decoded = decode(&input).unwrap();
+
+        assert_eq!(
+            disassemble(&input),
+            ["add cx, -1", "add cx, 65535", "sub ax, -1", "sub ax, 65535",]
+        );
+
+        // The flag comes from the s bit, not from the value
+        let signed = decoded
+            .iter()
+            .map(|d| match d.instruction {
+                Instruction::Add {
+                    src: Operand::Immediate { value, signed, .. },
+                    ..
+                }
+                | Instruction::Sub {
+                    src: Operand::Immediate { value, signed, .. },
+                    ..
+                } => (value, signed),
+                _ => panic!("expected an immediate"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            signed,
+            [
+                (0xffff, true),
+                (0xffff, false),
+                (0xffff, true),
+                (0xffff, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn lds_and_les() {
+        assert_eq!(
+            disassemble(&[0xc5, 0x1c, 0xc4, 0x46, 0x02, 0xc4, 0x06, 0x10, 0x00]),
+            ["lds bx, [si]", "les ax, [bp + 2]", "les ax, [16]"]
+        );
+    }
+
+    #[test]
+    fn les_needs_a_memory_operand() {
+        // les ax, ax
+        assert!(decode_error(&[0xc4, 0xc0]).contains("les needs a memory operand"));
+    }
+
+    #[test]
+    fn coverage_table() {
+        let mut out = Vec::new();
+        print_coverage(&mut out);
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 19);
+        assert_eq!(lines[0], "     0 1 2 3 4 5 6 7 8 9 a b c d e f");
+        // The mov family, and lock, repne and rep before hlt
+        assert_eq!(lines[9], "8_   x x x x x x x x x x x x x x x x");
+        assert!(lines[16].starts_with("f_   p . p p x"));
+
+        let implemented = lines[1..17]
+            .iter()
+            .map(|line| line.matches('x').count())
+            .sum::<usize>();
+        assert_eq!(lines[18], format!("Implemented: {implemented}/256"));
+    }
+
+    #[test]
+    fn near_displacements_are_from_the_next_instruction() {
+        // call forward to the nop, jmp back to the call
+        let input = [0xe8, 0x03, 0x00, 0xe9, 0xfa, 0xff, 0x90, 0xf4];
+        let decoded = decode(&input).unwrap();
+
+        assert_eq!(
+            disassemble(&input),
+            ["call near label_1", "jmp near label_0", "nop", "hlt"]
+        );
+        assert_eq!(decoded[0].label, Some(0));
+        assert_eq!(decoded[2].label, Some(1));
+        assert_eq!(decoded[2].offset, 3 + 3);
+        match (decoded[0].instruction, decoded[1].instruction) {
+            (
+                Instruction::Call {
+                    target: BranchTarget::Near(call),
+                },
+                Instruction::Jmp {
+                    target: BranchTarget::Near(jmp),
+                },
+            ) => {
+                assert_eq!(call.displacement(), 3);
+                assert_eq!(jmp.displacement(), -6);
+            }
+            _ => panic!("expected a near call and jmp"),
+        }
+    }
+
+    #[test]
+    fn near_targets_outside_the_input_stay_relative() {
+        // -32768 from the end of the jmp, relative to its start in NASM's $ notation
+        assert_eq!(disassemble(&[0xe9, 0x00, 0x80]), ["jmp near $-32765"]);
+        assert_eq!(disassemble(&[0xe9, 0x00, 0x10]), ["jmp near $+4099"]);
+    }
+
+    #[test]
+    fn backward_near_target_wraps_around_the_segment() {
+        // jmp 4 back from 3 lands on the last byte of the 64K segment
+        let mut input = vec![0x90; 0x10000];
+        input[..3].copy_from_slice(&[0xe9, 0xfc, 0xff]);
+        let decoded = decode(&input).unwrap();
+
+        assert_eq!(decoded[0].to_string(), "jmp near label_0");
+        let last = decoded.last().unwrap();
+        assert_eq!((last.offset, last.label), (0xffff, Some(0)));
+    }
+
+    #[test]
+    fn indirect_call_and_jmp() {
+        assert_eq!(
+            disassemble(&[
+                0xff, 0xd3, 0xff, 0x17, 0xff, 0x1e, 0x10, 0x00, 0xff, 0xe0, 0xff, 0x67, 0x02, 0xff,
+                0x2e, 0x10, 0x00,
+            ]),
+            [
+                "call bx",
+                "call word [bx]",
+                "call far [16]",
+                "jmp ax",
+                "jmp word [bx + 2]",
+                "jmp far [16]",
+            ]
+        );
+    }
+
+    #[test]
+    fn adc_and_sbb() {
+        assert_eq!(
+            disassemble(&[
+                0x11, 0xcb, 0x19, 0xcb, 0x15, 0x34, 0x12, 0x1c, 0x05, 0x81, 0xd3, 0x34, 0x12, 0x83,
+                0xdb, 0x01,
+            ]),
+            [
+                "adc bx, cx",
+                "sbb bx, cx",
+                "adc ax, 4660",
+                "sbb al, 5",
+                "adc bx, 4660",
+                "sbb bx, 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn arithmetic_share_one_decode_path() {
+        // op reg/mem, reg with the operation in bits 5..3 of the opcode
+        let names = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+        for (op, name) in names.iter().enumerate() {
+            let opcode = (op as u8) << 3;
+            assert_eq!(
+                disassemble(&[
+                    opcode,
+                    0xd8,
+                    opcode | 0b11,
+                    0x07,
+                    opcode | 0b101,
+                    0x01,
+                    0x00
+                ]),
+                [
+                    format!("{name} al, bl"),
+                    format!("{name} ax, [bx]"),
+                    format!("{name} ax, 1"),
+                ]
+            );
+        }
+    }
+}
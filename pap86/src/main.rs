@@ -1,367 +1,554 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use pap86::{
+    check_lengths, clocks, decode, decode_files, encode, parse_hex, print_coverage, sim::Simulator,
+    DecodeError, Decoded,
+};
 use std::{
-    cmp::Ordering,
-    fmt::Display,
+    cmp::Reverse,
+    collections::BTreeMap,
     fs::{self, File},
-    io::Write,
+    io::{self, BufWriter, Read, Write},
+    process,
 };
-use strum_macros::{AsRefStr, FromRepr};
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Format {
+    /// NASM assembly
+    Asm,
+    /// Decoded instructions with their offsets and lengths
+    Json,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Radix {
+    Dec,
+    /// Lowercase with a 0x prefix
+    Hex,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Decode and re-encode every listing in a directory, checking the bytes come back the same
+    /// without needing NASM
+    Selftest {
+        /// Directory holding the assembled listing_* files
+        #[arg(default_value = "perfaware/part1")]
+        dir: String,
+    },
+}
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// File to disassemble
-    file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Files to disassemble one after the other, reads from stdin when none are given
+    files: Vec<String>,
+
+    /// Decode bytes written as hex, e.g. "89 d9 88 cd", instead of reading files
+    #[arg(long, value_name = "BYTES", conflicts_with_all = ["files", "hex_file"])]
+    hex: Option<String>,
+
+    /// Decode the bytes written as hex in a text file
+    #[arg(long, value_name = "FILE", conflicts_with = "files")]
+    hex_file: Option<String>,
 
     /// Output file
     #[arg(long, short)]
     output: Option<String>,
-}
 
-#[derive(Copy, Clone, Debug)]
-enum Opcode {
-    MovRegToRegOrRegToMem,
-    MovImmediateToMem,
-    MovImmediateToReg,
-    MovMemToAcc,
-    MovAccToMem,
-}
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Asm)]
+    format: Format,
 
-impl Opcode {
-    fn parse(byte: u8) -> Opcode {
-        if byte & 0b1111_1100 == 0b1000_1000 {
-            return Opcode::MovRegToRegOrRegToMem;
-        }
+    /// Annotate each instruction with its estimated 8086 clocks
+    #[arg(long)]
+    cycles: bool,
 
-        if byte & 0b1111_1110 == 0b1100_0110 {
-            return Opcode::MovImmediateToMem;
-        }
+    /// Prefix each instruction with its encoded bytes in hex
+    #[arg(long)]
+    show_bytes: bool,
 
-        if byte & 0b1111_0000 == 0b1011_0000 {
-            return Opcode::MovImmediateToReg;
-        }
+    /// Print a listing with the address and bytes of each instruction before it
+    #[arg(long)]
+    listing: bool,
 
-        if byte & 0b1111_1110 == 0b1010_0000 {
-            return Opcode::MovMemToAcc;
-        }
+    /// Pad mnemonics so the operands of every instruction start in the same column
+    #[arg(long)]
+    align: bool,
 
-        if byte & 0b1111_1110 == 0b1010_0010 {
-            return Opcode::MovAccToMem;
-        }
+    /// Only print these instructions, by mnemonic as in --stats, e.g. mov,add
+    #[arg(long, value_name = "MNEMONICS", value_delimiter = ',')]
+    only: Vec<String>,
 
-        panic!("Invalid opcode: {byte:b}");
-    }
+    /// Don't print these instructions, by mnemonic as in --stats
+    #[arg(long, value_name = "MNEMONICS", value_delimiter = ',')]
+    exclude: Vec<String>,
+
+    /// Print a comment counting the instructions --only or --exclude left out where they were
+    #[arg(long)]
+    mark_hidden: bool,
+
+    /// Radix of immediates, displacements and addresses
+    #[arg(long, value_enum, default_value_t = Radix::Dec)]
+    radix: Radix,
+
+    /// Address the code is loaded at, e.g. 0x100 for a .com file
+    #[arg(long, value_name = "ADDR", default_value = "0", value_parser = parse_address)]
+    org: u16,
+
+    /// Print which first opcode bytes can be decoded and exit
+    #[arg(long)]
+    coverage: bool,
+
+    /// Stop decoding after this many instructions, printing how far into the input that was
+    #[arg(long, value_name = "N", alias = "max-instructions")]
+    limit: Option<usize>,
+
+    /// Emit bytes that can't be decoded as db and carry on from the next byte
+    #[arg(long)]
+    skip_unknown: bool,
+
+    /// Check that the decoded instruction lengths add up to exactly the input
+    #[arg(long)]
+    strict: bool,
+
+    /// Check that re-encoding the decoded instructions reproduces the input
+    #[arg(long)]
+    verify: bool,
+
+    /// Print how many of each instruction were decoded instead of disassembling
+    #[arg(long)]
+    stats: bool,
+
+    /// Execute the instructions and print the final registers instead of disassembling
+    #[arg(long)]
+    exec: bool,
+
+    /// Print the registers, ip and flags each executed instruction changed, requires --exec
+    #[arg(long, requires = "exec")]
+    trace: bool,
+
+    /// Write the final simulated memory image to a file, requires --exec
+    #[arg(long, value_name = "FILE", requires = "exec")]
+    dump_memory: Option<String>,
 }
 
-// Register from encoding W | REG
-#[derive(AsRefStr, Copy, Clone, Debug, FromRepr)]
-#[repr(u8)]
-enum Register {
-    AL = 0b0000,
-    CL = 0b0001,
-    DL = 0b0010,
-    BL = 0b0011,
-    AH = 0b0100,
-    CH = 0b0101,
-    DH = 0b0110,
-    BH = 0b0111,
-    AX = 0b1000,
-    CX = 0b1001,
-    DX = 0b1010,
-    BX = 0b1011,
-    SP = 0b1100,
-    BP = 0b1101,
-    SI = 0b1110,
-    DI = 0b1111,
+// Decimal, or hex with a 0x prefix
+fn parse_address(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("{e}"))
 }
 
-#[derive(Copy, Clone, Debug, FromRepr)]
-#[repr(u8)]
-enum EffectiveAddressFormula {
-    BxPlusSi = 0b000,
-    BxPlusDi = 0b001,
-    BpPlusSi = 0b010,
-    BpPlusDi = 0b011,
-    Si = 0b100,
-    Di = 0b101,
-    Bp = 0b110,
-    Bx = 0b111,
+struct OutputOptions {
+    cycles: bool,
+    show_bytes: bool,
+    listing: bool,
+    align: bool,
+    hex: bool,
+    only: Vec<String>,
+    exclude: Vec<String>,
+    mark_hidden: bool,
+    org: u16,
+    // Name and start offset of each file, a comment heads each one's instructions when there are
+    // several
+    files: Vec<(String, usize)>,
 }
 
-impl Display for EffectiveAddressFormula {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            EffectiveAddressFormula::BxPlusSi => write!(f, "bx + si"),
-            EffectiveAddressFormula::BxPlusDi => write!(f, "bx + di"),
-            EffectiveAddressFormula::BpPlusSi => write!(f, "bp + si"),
-            EffectiveAddressFormula::BpPlusDi => write!(f, "bp + di"),
-            EffectiveAddressFormula::Si => write!(f, "si"),
-            EffectiveAddressFormula::Di => write!(f, "di"),
-            EffectiveAddressFormula::Bp => write!(f, "bp"),
-            EffectiveAddressFormula::Bx => write!(f, "bx"),
-        }
+impl OutputOptions {
+    fn shows(&self, decoded: &Decoded) -> bool {
+        let mnemonic = decoded.instruction.mnemonic();
+        (self.only.is_empty() || self.only.iter().any(|m| m == mnemonic))
+            && !self.exclude.iter().any(|m| m == mnemonic)
     }
 }
 
-fn displacement_str(displacement: &Option<i16>) -> String {
-    if let Some(displacement) = displacement {
-        match displacement.cmp(&0) {
-            Ordering::Greater => format!(" + {displacement}"),
-            Ordering::Less => format!(" - {}", displacement.abs()),
-            Ordering::Equal => "".to_string(),
-        }
-    } else {
-        "".to_string()
+// {:#} lines up the operands, {:x} writes the numbers in hex
+fn render(decoded: &Decoded, align: bool, hex: bool) -> String {
+    match (align, hex) {
+        (true, true) => format!("{decoded:#x}"),
+        (true, false) => format!("{decoded:#}"),
+        (false, true) => format!("{decoded:x}"),
+        (false, false) => decoded.to_string(),
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-enum Operand {
-    Register(Register),
-    Mem {
-        formula: EffectiveAddressFormula,
-        displacement: Option<i16>,
-    },
-    MemDirect(u16),
-    Immediate(u16, bool),
+fn write_hidden(w: &mut dyn Write, hidden: &mut usize, options: &OutputOptions) {
+    if options.mark_hidden && *hidden > 0 {
+        let s = if *hidden == 1 { "" } else { "s" };
+        writeln!(w, "; ... {hidden} instruction{s} hidden").unwrap();
+    }
+    *hidden = 0;
 }
 
-impl Display for Operand {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Operand::Register(reg) => write!(f, "{}", reg.as_ref().to_lowercase()),
-            Operand::Mem {
-                formula,
-                displacement,
-            } => {
-                write!(f, "[{}{}]", formula, displacement_str(displacement),)
-            }
-            Operand::MemDirect(address) => {
-                write!(f, "[{}]", address)
+// Writes the instructions as they come, only going over them an extra time to size the bytes column
+fn output<'a>(
+    w: &mut dyn Write,
+    input: &[u8],
+    instructions: impl Iterator<Item = &'a Decoded> + Clone,
+    options: &OutputOptions,
+) {
+    writeln!(w, "bits 16").unwrap();
+
+    // Direct addresses and labels are where NASM puts them once it knows the load address
+    if options.org != 0 {
+        writeln!(w, "org {}", options.org).unwrap();
+    }
+
+    let mut total_clocks = 0;
+
+    // Room for the longest instruction's bytes so the mnemonics line up
+    let bytes_width = if options.show_bytes || options.listing {
+        instructions.clone().map(|d| d.len).max().unwrap_or(0) * 3 + 2
+    } else {
+        0
+    };
+
+    let mut files = options.files.iter().peekable();
+
+    // Instructions filtered out since the last one printed
+    let mut hidden = 0;
+
+    for decoded in instructions {
+        while let Some((name, _)) = files.next_if(|(_, start)| *start <= decoded.offset) {
+            if options.files.len() > 1 {
+                write_hidden(w, &mut hidden, options);
+                writeln!(w, "; ---- {name} ----").unwrap();
             }
-            Operand::Immediate(value, needs_size) => {
-                write!(
-                    f,
-                    "{}",
-                    if *needs_size {
-                        if *value > 255 {
-                            format!("word {value}")
-                        } else {
-                            format!("byte {value}")
-                        }
-                    } else {
-                        format!("{value}")
-                    }
-                )
+        }
+
+        // Hidden instructions still count towards the running total
+        let clocks = clocks::estimate(&decoded.instruction).filter(|_| options.cycles);
+        total_clocks += clocks.as_ref().map_or(0, |clocks| clocks.total());
+
+        if !options.shows(decoded) {
+            hidden += 1;
+            continue;
+        }
+        write_hidden(w, &mut hidden, options);
+
+        if let Some(label) = decoded.label {
+            // In a listing labels line up with the instructions, past the address and bytes
+            if options.listing {
+                write!(w, "{:1$}", "", 6 + bytes_width).unwrap();
             }
+            writeln!(w, "label_{label}:").unwrap();
         }
-    }
-}
 
-#[derive(Copy, Clone, Debug)]
-enum Instruction {
-    Mov { dst: Operand, src: Operand },
-}
+        if options.listing {
+            write!(w, "{:04x}  ", decoded.offset + options.org as usize).unwrap();
+        }
+
+        if options.show_bytes || options.listing {
+            let bytes = input[decoded.offset..decoded.offset + decoded.len]
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            write!(w, "{bytes:bytes_width$}").unwrap();
+        }
+
+        write!(w, "{}", render(decoded, options.align, options.hex)).unwrap();
 
-impl Display for Instruction {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Instruction::Mov { dst, src } => {
-                write!(f, "mov {}, {}", dst, src)
+        if let Some(clocks) = clocks {
+            write!(w, " ; Clocks: +{} = {total_clocks}", clocks.total()).unwrap();
+            if clocks.ea > 0 {
+                write!(w, " ({} + {}ea)", clocks.base, clocks.ea).unwrap();
             }
         }
+
+        writeln!(w).unwrap();
     }
-}
 
-struct Input<'a> {
-    input: &'a [u8],
-    index: usize,
+    write_hidden(w, &mut hidden, options);
 }
 
-impl<'a> Input<'a> {
-    fn new(input: &[u8]) -> Input {
-        Input { input, index: 0 }
+// Most common first, ties in alphabetical order
+fn print_stats(w: &mut dyn Write, instructions: &[Decoded]) {
+    let mut counts = BTreeMap::<&str, usize>::new();
+    for decoded in instructions {
+        *counts.entry(decoded.instruction.mnemonic()).or_default() += 1;
     }
 
-    fn next_byte(&mut self) -> u8 {
-        let byte = self.input[self.index];
-        self.index += 1;
-        byte
-    }
-
-    fn next_word(&mut self) -> u16 {
-        let lo = self.next_byte() as u16;
-        let hi = self.next_byte() as u16;
-        (hi << 8) | lo
-    }
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by_key(|(_, count)| Reverse(*count));
 
-    fn is_empty(&self) -> bool {
-        self.index == self.input.len()
+    let width = counts
+        .iter()
+        .map(|(name, _)| name.len())
+        .chain(["total".len()])
+        .max()
+        .unwrap();
+    for (name, count) in counts {
+        writeln!(w, "{name:>width$}: {count}").unwrap();
     }
+    writeln!(w, "{:>width$}: {}", "total", instructions.len()).unwrap();
 }
 
-fn parse_mem(input: &mut Input, w: u8, instruction_byte_2: u8) -> Result<Operand, String> {
-    let mode = instruction_byte_2 >> 6;
-    let mem = instruction_byte_2 & 0b111;
-
-    Ok(match mode {
-        0b00 => {
-            if mem == 0b110 {
-                Operand::MemDirect(input.next_word())
-            } else {
-                Operand::Mem {
-                    formula: EffectiveAddressFormula::from_repr(mem)
-                        .ok_or_else(|| format!("Invalid formula: {mem:b}"))?,
-                    displacement: None,
-                }
-            }
-        }
-        0b01 => Operand::Mem {
-            formula: EffectiveAddressFormula::from_repr(mem)
-                .ok_or_else(|| format!("Invalid formula: {mem:b}"))?,
-            displacement: Some(input.next_byte() as i8 as i16),
-        },
-        0b10 => Operand::Mem {
-            formula: EffectiveAddressFormula::from_repr(mem)
-                .ok_or_else(|| format!("Invalid formula: {mem:b}"))?,
-            displacement: Some(input.next_word() as i16),
-        },
-        0b11 => {
-            let w_reg_2 = (w << 3) | mem;
-
-            Register::from_repr(w_reg_2)
-                .map(Operand::Register)
-                .ok_or_else(|| format!("Invalid reg: {w_reg_2:b}"))?
+fn execute(
+    w: &mut dyn Write,
+    instructions: &[Decoded],
+    trace: bool,
+    hex: bool,
+    dump_memory: Option<&str>,
+) {
+    let mut simulator = Simulator::default();
+
+    // Runs until ip leaves the program, lands inside an instruction or reaches a hlt
+    while let Ok(index) =
+        instructions.binary_search_by_key(&(simulator.ip() as usize), |d| d.offset)
+    {
+        let decoded = &instructions[index];
+
+        let diff = simulator.step(&decoded.instruction, decoded.len as u16);
+        let (old_flags, new_flags) = diff.flags;
+
+        let decoded_str = render(decoded, false, hex);
+        if trace {
+            writeln!(w, "{decoded_str} ;{diff}").unwrap();
+        } else if old_flags != new_flags {
+            writeln!(w, "{decoded_str} ; flags: {old_flags}->{new_flags}").unwrap();
+        } else {
+            writeln!(w, "{decoded_str}").unwrap();
         }
-        _ => Err("Invalid mode".to_string())?,
-    })
-}
-
-fn decode(input: &[u8]) -> Vec<Instruction> {
-    let mut input = Input::new(input);
-    let mut res = Vec::new();
 
-    while !input.is_empty() {
-        let instruction_byte_1 = input.next_byte();
-
-        let opcode = Opcode::parse(instruction_byte_1);
-
-        let instruction = match opcode {
-            Opcode::MovRegToRegOrRegToMem => {
-                let d = (instruction_byte_1 >> 1) & 0b1;
-                let w = instruction_byte_1 & 0b1;
+        if decoded.instruction.mnemonic() == "hlt" {
+            break;
+        }
+    }
+    writeln!(w).unwrap();
+    simulator.dump(w);
 
-                let instruction_byte_2 = input.next_byte();
+    if let Some(file) = dump_memory {
+        fs::write(file, simulator.memory()).unwrap();
+    }
+}
 
-                let w_reg_1 = (w << 3) | ((instruction_byte_2 >> 3) & 0b111);
+fn reencode(input: &[u8], instructions: &[Decoded]) -> Result<(), String> {
+    let encoded = encode::encode(
+        &instructions
+            .iter()
+            .map(|d| d.instruction)
+            .collect::<Vec<_>>(),
+    )?;
+
+    match (0..input.len().max(encoded.len())).find(|&i| input.get(i) != encoded.get(i)) {
+        Some(offset) => Err(format!(
+            "re-encoded bytes differ from the input at offset {offset}"
+        )),
+        None => Ok(()),
+    }
+}
 
-                let reg_1 = Register::from_repr(w_reg_1)
-                    .map(Operand::Register)
-                    .ok_or_else(|| format!("Invalid reg: {w_reg_1:b}"))
-                    .unwrap();
+fn verify(input: &[u8], instructions: &[Decoded]) {
+    if let Err(e) = reencode(input, instructions) {
+        eprintln!("Verify failed: {e}");
+        process::exit(1);
+    }
+}
 
-                let mem = parse_mem(&mut input, w, instruction_byte_2).unwrap();
+// Every listing_* binary in dir with its .asm source next to it, the same ones pap86_runner
+// checks. Listings the decoder can't handle yet are skipped rather than failed
+fn selftest(dir: &str) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Unable to read {dir}: {e}");
+            process::exit(1);
+        }
+    };
 
-                if d > 0 {
-                    Instruction::Mov {
-                        dst: reg_1,
-                        src: mem,
-                    }
-                } else {
-                    Instruction::Mov {
-                        dst: mem,
-                        src: reg_1,
-                    }
-                }
+    let mut listings = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?.to_owned();
+
+            let is_binary = name.starts_with("listing_") && path.extension().is_none();
+            let has_source = path.with_extension("asm").exists();
+
+            (is_binary && has_source).then_some((name, path))
+        })
+        .collect::<Vec<_>>();
+    listings.sort();
+
+    let width = listings
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let (mut passed, mut skipped, mut failed) = (0, 0, 0);
+
+    for (name, path) in &listings {
+        let input = fs::read(path).unwrap();
+
+        match decode(&input).map(|instructions| reencode(&input, &instructions)) {
+            Ok(Ok(())) => {
+                passed += 1;
+                println!("{name:width$}  PASS");
             }
-            Opcode::MovImmediateToMem => {
-                let w = instruction_byte_1 & 0b1;
-
-                let instruction_byte_2 = input.next_byte();
-
-                let mem = parse_mem(&mut input, w, instruction_byte_2).unwrap();
-
-                let data = Operand::Immediate(
-                    if w > 0 {
-                        input.next_word()
-                    } else {
-                        input.next_byte() as u16
-                    },
-                    true,
-                );
-
-                Instruction::Mov {
-                    dst: mem,
-                    src: data,
-                }
+            Ok(Err(e)) => {
+                failed += 1;
+                println!("{name:width$}  FAIL  {e}");
             }
-            Opcode::MovImmediateToReg => {
-                let w_reg = instruction_byte_1 & 0b1111;
-
-                let dst = Register::from_repr(w_reg)
-                    .map(Operand::Register)
-                    .ok_or_else(|| format!("Invalid reg: {w_reg:b}"))
-                    .unwrap();
-
-                let data = Operand::Immediate(
-                    if w_reg & 0b1000 > 0 {
-                        input.next_word()
-                    } else {
-                        input.next_byte() as u16
-                    },
-                    false,
-                );
-
-                Instruction::Mov { dst, src: data }
+            // Only opcodes the decoder has no entry for yet are skipped
+            Err(e) if matches!(&e, DecodeError::Invalid { reason, .. } if reason.starts_with("Invalid opcode")) =>
+            {
+                skipped += 1;
+                println!("{name:width$}  SKIP  {e}");
             }
-            Opcode::MovMemToAcc => {
-                let w = instruction_byte_1 & 0b1;
+            Err(e) => {
+                failed += 1;
+                println!("{name:width$}  FAIL  {e}");
+            }
+        }
+    }
 
-                let addr = Operand::MemDirect(input.next_word());
+    println!();
+    println!("{passed} passed, {skipped} skipped, {failed} failed");
 
-                Instruction::Mov {
-                    dst: Operand::Register(if w > 0 { Register::AX } else { Register::AL }),
-                    src: addr,
-                }
-            }
-            Opcode::MovAccToMem => {
-                let w = instruction_byte_1 & 0b1;
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let cli = Args::parse();
 
-                let addr = Operand::MemDirect(input.next_word());
+    if let Some(Command::Selftest { dir }) = &cli.command {
+        selftest(dir);
+        return;
+    }
+
+    if cli.coverage {
+        print_coverage(&mut io::stdout());
+        return;
+    }
 
-                Instruction::Mov {
-                    dst: addr,
-                    src: Operand::Register(if w > 0 { Register::AX } else { Register::AL }),
-                }
+    let mut input = Vec::new();
+    let mut files = Vec::new();
+
+    let hex = match (&cli.hex, &cli.hex_file) {
+        (Some(hex), _) => Some(("hex".to_string(), hex.clone())),
+        (_, Some(file)) => Some((
+            file.clone(),
+            fs::read_to_string(file)
+                .map_err(|e| panic!("Unable to read {file}: {e:?}"))
+                .unwrap(),
+        )),
+        _ => None,
+    };
+
+    if let Some((name, hex)) = hex {
+        input = match parse_hex(&hex) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Unable to parse {name}: {e}");
+                process::exit(1);
             }
         };
+        files.push((name, 0));
+    } else if cli.files.is_empty() {
+        io::stdin()
+            .read_to_end(&mut input)
+            .map_err(|e| panic!("Unable to read stdin: {e:?}"))
+            .unwrap();
+        files.push(("stdin".to_string(), 0));
+    }
 
-        res.push(instruction);
+    for file in &cli.files {
+        files.push((file.clone(), input.len()));
+        input.extend(
+            fs::read(file)
+                .map_err(|e| panic!("Unable to read {file}: {e:?}"))
+                .unwrap(),
+        );
     }
 
-    res
-}
+    let ends = files
+        .iter()
+        .skip(1)
+        .map(|(_, start)| *start)
+        .chain([input.len()])
+        .collect::<Vec<_>>();
+
+    let instructions = match decode_files(&input, &ends, cli.skip_unknown, cli.limit) {
+        Ok(instructions) => instructions,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
 
-fn output(w: &mut dyn Write, instructions: &[Instruction]) {
-    writeln!(w, "bits 16").unwrap();
-    for instruction in instructions {
-        writeln!(w, "{instruction}").unwrap();
+    // A limit can stop decoding short of the end, the checks then cover what was decoded
+    let decoded_len = match cli.limit {
+        Some(_) => instructions.last().map_or(0, |d| d.offset + d.len),
+        None => input.len(),
+    };
+    if decoded_len < input.len() {
+        eprintln!(
+            "Stopped after {} instructions at offset {decoded_len} of {} bytes",
+            instructions.len(),
+            input.len()
+        );
     }
-}
-
-fn main() {
-    let cli = Args::parse();
 
-    let input = fs::read(&cli.file)
-        .map_err(|e| panic!("Unable to read {}: {e:?}", &cli.file))
-        .unwrap();
+    if cli.strict {
+        if let Err(e) = check_lengths(&instructions, decoded_len) {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    }
 
-    let instructions = decode(&input);
+    if cli.verify {
+        verify(&input[..decoded_len], &instructions);
+    }
 
-    if let Some(file) = cli.output {
-        let mut file = File::create(file).unwrap();
-        output(&mut file, &instructions);
+    // Buffered so every line isn't its own write, flushed once everything has been written
+    let mut w: Box<dyn Write> = if let Some(file) = cli.output {
+        Box::new(BufWriter::new(File::create(file).unwrap()))
     } else {
-        output(&mut std::io::stdout(), &instructions);
+        Box::new(BufWriter::new(io::stdout().lock()))
     };
+
+    if cli.stats {
+        print_stats(&mut w, &instructions);
+    } else if cli.exec {
+        execute(
+            &mut w,
+            &instructions,
+            cli.trace,
+            matches!(cli.radix, Radix::Hex),
+            cli.dump_memory.as_deref(),
+        );
+    } else {
+        match cli.format {
+            Format::Asm => output(
+                &mut w,
+                &input,
+                instructions.iter(),
+                &OutputOptions {
+                    cycles: cli.cycles,
+                    show_bytes: cli.show_bytes,
+                    listing: cli.listing,
+                    align: cli.align,
+                    hex: matches!(cli.radix, Radix::Hex),
+                    only: cli.only,
+                    exclude: cli.exclude,
+                    mark_hidden: cli.mark_hidden,
+                    org: cli.org,
+                    files,
+                },
+            ),
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut w, &instructions).unwrap();
+                writeln!(w).unwrap();
+            }
+        }
+    }
+
+    w.flush().unwrap();
 }
@@ -0,0 +1,758 @@
+use crate::{
+    BranchTarget, EffectiveAddressFormula, Instruction, Operand, Register, SegmentRegister,
+};
+use std::{fmt::Display, io::Write};
+
+// Order registers are listed in when dumping, matching the reference output
+static DUMP_ORDER: [Register; 8] = [
+    Register::AX,
+    Register::BX,
+    Register::CX,
+    Register::DX,
+    Register::SP,
+    Register::BP,
+    Register::SI,
+    Register::DI,
+];
+
+static SEGMENT_DUMP_ORDER: [SegmentRegister; 4] = [
+    SegmentRegister::ES,
+    SegmentRegister::CS,
+    SegmentRegister::SS,
+    SegmentRegister::DS,
+];
+
+// The 8 word registers, with AL..BH aliasing the low and high bytes of AX..BX, and the segment registers
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct RegisterFile {
+    // Indexed by the low 3 bits of the W | REG encoding
+    words: [u16; 8],
+    // Indexed by the SR encoding
+    segments: [u16; 4],
+}
+
+impl RegisterFile {
+    pub fn read8(&self, reg: Register) -> u8 {
+        let encoding = reg as u8;
+        debug_assert!(encoding & 0b1000 == 0, "{reg:?} is not a byte register");
+
+        let word = self.words[(encoding & 0b11) as usize];
+        if encoding & 0b100 > 0 {
+            (word >> 8) as u8
+        } else {
+            word as u8
+        }
+    }
+
+    pub fn write8(&mut self, reg: Register, value: u8) {
+        let encoding = reg as u8;
+        debug_assert!(encoding & 0b1000 == 0, "{reg:?} is not a byte register");
+
+        let word = &mut self.words[(encoding & 0b11) as usize];
+        if encoding & 0b100 > 0 {
+            *word = (*word & 0x00ff) | ((value as u16) << 8);
+        } else {
+            *word = (*word & 0xff00) | value as u16;
+        }
+    }
+
+    pub fn read16(&self, reg: Register) -> u16 {
+        let encoding = reg as u8;
+        debug_assert!(encoding & 0b1000 > 0, "{reg:?} is not a word register");
+
+        self.words[(encoding & 0b111) as usize]
+    }
+
+    pub fn write16(&mut self, reg: Register, value: u16) {
+        let encoding = reg as u8;
+        debug_assert!(encoding & 0b1000 > 0, "{reg:?} is not a word register");
+
+        self.words[(encoding & 0b111) as usize] = value;
+    }
+
+    pub fn read_segment(&self, reg: SegmentRegister) -> u16 {
+        self.segments[reg as usize]
+    }
+
+    pub fn write_segment(&mut self, reg: SegmentRegister, value: u16) {
+        self.segments[reg as usize] = value;
+    }
+
+    // Reads at the width of the register, byte registers are zero extended
+    pub fn read(&self, reg: Register) -> u16 {
+        if reg as u8 & 0b1000 > 0 {
+            self.read16(reg)
+        } else {
+            self.read8(reg) as u16
+        }
+    }
+
+    // Writes at the width of the register, byte registers take the low byte of value
+    pub fn write(&mut self, reg: Register, value: u16) {
+        if reg as u8 & 0b1000 > 0 {
+            self.write16(reg, value);
+        } else {
+            self.write8(reg, value as u8);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub carry: bool,
+    pub parity: bool,
+    pub auxiliary: bool,
+    pub zero: bool,
+    pub sign: bool,
+    pub interrupt: bool,
+    pub direction: bool,
+    pub overflow: bool,
+}
+
+impl Display for Flags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // In the order of the reference simulator's output
+        let flags = [
+            (self.carry, "C"),
+            (self.parity, "P"),
+            (self.auxiliary, "A"),
+            (self.zero, "Z"),
+            (self.sign, "S"),
+            (self.interrupt, "I"),
+            (self.direction, "D"),
+            (self.overflow, "O"),
+        ];
+        for (set, name) in flags {
+            if set {
+                write!(f, "{name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// What executing one instruction changed, registers are listed as whole words in dump order
+pub struct StepDiff {
+    pub registers: Vec<(&'static str, u16, u16)>,
+    pub ip: (u16, u16),
+    pub flags: (Flags, Flags),
+}
+
+// (name, old, new) of each register that differs between the two
+fn changed_registers(before: &RegisterFile, after: &RegisterFile) -> Vec<(&'static str, u16, u16)> {
+    let words = DUMP_ORDER
+        .iter()
+        .map(|reg| (reg.as_ref(), before.read16(*reg), after.read16(*reg)));
+    let segments = SEGMENT_DUMP_ORDER.iter().map(|reg| {
+        (
+            reg.as_ref(),
+            before.read_segment(*reg),
+            after.read_segment(*reg),
+        )
+    });
+
+    words
+        .chain(segments)
+        .filter(|(_, old, new)| old != new)
+        .collect()
+}
+
+// Formatted as ` ax:0x0->0x1 ip:0x0->0x3 flags:->Z` to follow an instruction
+impl Display for StepDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, old, new) in &self.registers {
+            write!(f, " {}:0x{old:x}->0x{new:x}", name.to_lowercase())?;
+        }
+        write!(f, " ip:0x{:x}->0x{:x}", self.ip.0, self.ip.1)?;
+        if self.flags.0 != self.flags.1 {
+            write!(f, " flags:{}->{}", self.flags.0, self.flags.1)?;
+        }
+        Ok(())
+    }
+}
+
+// Width of an operation, taken from whichever operand has an implied size
+fn is_wide(dst: &Operand, src: &Operand) -> bool {
+    match (dst, src) {
+        (Operand::Register(reg), _) | (_, Operand::Register(reg)) => *reg as u8 & 0b1000 > 0,
+        (_, Operand::Immediate { wide, .. }) => *wide,
+        _ => true,
+    }
+}
+
+const MEMORY_SIZE: usize = 1024 * 1024;
+
+pub struct Simulator {
+    registers: RegisterFile,
+    flags: Flags,
+    ip: u16,
+    memory: Vec<u8>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Simulator {
+            registers: RegisterFile::default(),
+            flags: Flags::default(),
+            ip: 0,
+            memory: vec![0; MEMORY_SIZE],
+        }
+    }
+}
+
+impl Simulator {
+    fn effective_address(
+        &self,
+        formula: EffectiveAddressFormula,
+        displacement: Option<i16>,
+    ) -> u16 {
+        let r = |reg| self.registers.read16(reg);
+
+        let base = match formula {
+            EffectiveAddressFormula::BxPlusSi => r(Register::BX).wrapping_add(r(Register::SI)),
+            EffectiveAddressFormula::BxPlusDi => r(Register::BX).wrapping_add(r(Register::DI)),
+            EffectiveAddressFormula::BpPlusSi => r(Register::BP).wrapping_add(r(Register::SI)),
+            EffectiveAddressFormula::BpPlusDi => r(Register::BP).wrapping_add(r(Register::DI)),
+            EffectiveAddressFormula::Si => r(Register::SI),
+            EffectiveAddressFormula::Di => r(Register::DI),
+            EffectiveAddressFormula::Bp => r(Register::BP),
+            EffectiveAddressFormula::Bx => r(Register::BX),
+        };
+
+        base.wrapping_add(displacement.unwrap_or(0) as u16)
+    }
+
+    // Address of a memory operand, None for registers and immediates
+    fn address(&self, operand: &Operand) -> Option<usize> {
+        match operand {
+            Operand::Mem {
+                formula,
+                displacement,
+                ..
+            } => Some(self.effective_address(*formula, *displacement) as usize),
+            Operand::MemDirect { address, .. } => Some(*address as usize),
+            _ => None,
+        }
+    }
+
+    fn read(&self, operand: &Operand, wide: bool) -> u16 {
+        match operand {
+            Operand::Register(reg) => self.registers.read(*reg),
+            Operand::SegmentRegister(reg) => self.registers.read_segment(*reg),
+            Operand::Immediate { value, .. } => *value,
+            _ => {
+                let address = self.address(operand).unwrap();
+                let lo = self.memory[address] as u16;
+                if wide {
+                    let hi = self.memory[(address + 1) % MEMORY_SIZE] as u16;
+                    (hi << 8) | lo
+                } else {
+                    lo
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, operand: &Operand, value: u16, wide: bool) {
+        match operand {
+            Operand::Register(reg) => self.registers.write(*reg, value),
+            Operand::SegmentRegister(reg) => self.registers.write_segment(*reg, value),
+            Operand::Immediate { .. } => {}
+            _ => {
+                let address = self.address(operand).unwrap();
+                self.memory[address] = value as u8;
+                if wide {
+                    self.memory[(address + 1) % MEMORY_SIZE] = (value >> 8) as u8;
+                }
+            }
+        }
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Zero, sign and parity of a result, parity only looks at the low byte
+    fn set_flags(&mut self, result: u16, wide: bool) {
+        let sign_bit = if wide { 0x8000 } else { 0x80 };
+        let mask = if wide { 0xffff } else { 0xff };
+
+        self.flags.zero = result & mask == 0;
+        self.flags.sign = result & sign_bit > 0;
+        self.flags.parity = (result as u8).count_ones().is_multiple_of(2);
+    }
+
+    // a + b + carry, setting every arithmetic flag from the untruncated sum
+    fn add(&mut self, a: u16, b: u16, carry: bool, wide: bool) -> u16 {
+        let (mask, sign_bit) = if wide { (0xffff, 0x8000) } else { (0xff, 0x80) };
+        let (a, b) = (a & mask, b & mask);
+
+        let sum = a as u32 + b as u32 + carry as u32;
+        let result = sum as u16 & mask;
+
+        self.flags.carry = sum > mask as u32;
+        self.flags.auxiliary = (a & 0xf) + (b & 0xf) + carry as u16 > 0xf;
+        // Both operands have the same sign and the result the other one
+        self.flags.overflow = (a ^ result) & (b ^ result) & sign_bit > 0;
+        self.set_flags(result, wide);
+
+        result
+    }
+
+    // a - b - borrow, carry is set when the subtraction borrows
+    fn sub(&mut self, a: u16, b: u16, borrow: bool, wide: bool) -> u16 {
+        let (mask, sign_bit) = if wide { (0xffff, 0x8000) } else { (0xff, 0x80) };
+        let (a, b) = (a & mask, b & mask);
+
+        let difference = a as i32 - b as i32 - borrow as i32;
+        let result = difference as u16 & mask;
+
+        self.flags.carry = difference < 0;
+        self.flags.auxiliary = ((a & 0xf) as i32 - (b & 0xf) as i32 - (borrow as i32)) < 0;
+        // The operands have different signs and the result the sign of b
+        self.flags.overflow = (a ^ b) & (a ^ result) & sign_bit > 0;
+        self.set_flags(result, wide);
+
+        result
+    }
+
+    // and, or, xor and test clear carry and overflow, auxiliary is left undefined
+    fn set_logic_flags(&mut self, result: u16, wide: bool) {
+        self.flags.carry = false;
+        self.flags.overflow = false;
+        self.set_flags(result, wide);
+    }
+
+    // Whether a conditional jump or loop is taken, the loops count cx down first without
+    // touching the flags
+    fn jump_taken(&mut self, mnemonic: &str) -> bool {
+        let flags = self.flags;
+
+        match mnemonic {
+            "jo" => flags.overflow,
+            "jno" => !flags.overflow,
+            "jb" => flags.carry,
+            "jnb" => !flags.carry,
+            "je" => flags.zero,
+            "jne" => !flags.zero,
+            "jbe" => flags.carry || flags.zero,
+            "ja" => !flags.carry && !flags.zero,
+            "js" => flags.sign,
+            "jns" => !flags.sign,
+            "jp" => flags.parity,
+            "jnp" => !flags.parity,
+            "jl" => flags.sign != flags.overflow,
+            "jnl" => flags.sign == flags.overflow,
+            "jle" => flags.zero || flags.sign != flags.overflow,
+            "jg" => !flags.zero && flags.sign == flags.overflow,
+            "jcxz" => self.registers.read16(Register::CX) == 0,
+            _ => {
+                let cx = self.registers.read16(Register::CX).wrapping_sub(1);
+                self.registers.write16(Register::CX, cx);
+
+                match mnemonic {
+                    "loopz" => cx != 0 && flags.zero,
+                    "loopnz" => cx != 0 && !flags.zero,
+                    _ => cx != 0,
+                }
+            }
+        }
+    }
+
+    pub fn ip(&self) -> u16 {
+        self.ip
+    }
+
+    // Executes an instruction of len encoded bytes, leaving ip at the next instruction to run
+    pub fn step(&mut self, instruction: &Instruction, len: u16) -> StepDiff {
+        let registers = self.registers;
+        let ip = self.ip;
+        let flags = self.flags;
+
+        self.execute(instruction, len);
+
+        StepDiff {
+            registers: changed_registers(&registers, &self.registers),
+            ip: (ip, self.ip),
+            flags: (flags, self.flags),
+        }
+    }
+
+    fn execute(&mut self, instruction: &Instruction, len: u16) {
+        self.ip = self.ip.wrapping_add(len);
+
+        match instruction {
+            Instruction::Mov { dst, src } => {
+                let wide = is_wide(dst, src);
+                let value = self.read(src, wide);
+                self.write(dst, value, wide);
+            }
+            Instruction::Add { dst, src } => {
+                let wide = is_wide(dst, src);
+                let result = self.add(self.read(dst, wide), self.read(src, wide), false, wide);
+                self.write(dst, result, wide);
+            }
+            Instruction::Sub { dst, src } => {
+                let wide = is_wide(dst, src);
+                let result = self.sub(self.read(dst, wide), self.read(src, wide), false, wide);
+                self.write(dst, result, wide);
+            }
+            Instruction::Adc { dst, src } => {
+                let wide = is_wide(dst, src);
+                let carry = self.flags.carry;
+                let result = self.add(self.read(dst, wide), self.read(src, wide), carry, wide);
+                self.write(dst, result, wide);
+            }
+            Instruction::Sbb { dst, src } => {
+                let wide = is_wide(dst, src);
+                let borrow = self.flags.carry;
+                let result = self.sub(self.read(dst, wide), self.read(src, wide), borrow, wide);
+                self.write(dst, result, wide);
+            }
+            Instruction::Cmp { dst, src } => {
+                let wide = is_wide(dst, src);
+                self.sub(self.read(dst, wide), self.read(src, wide), false, wide);
+            }
+            Instruction::And { dst, src } => {
+                let wide = is_wide(dst, src);
+                let result = self.read(dst, wide) & self.read(src, wide);
+                self.set_logic_flags(result, wide);
+                self.write(dst, result, wide);
+            }
+            Instruction::Or { dst, src } => {
+                let wide = is_wide(dst, src);
+                let result = self.read(dst, wide) | self.read(src, wide);
+                self.set_logic_flags(result, wide);
+                self.write(dst, result, wide);
+            }
+            Instruction::Xor { dst, src } => {
+                let wide = is_wide(dst, src);
+                let result = self.read(dst, wide) ^ self.read(src, wide);
+                self.set_logic_flags(result, wide);
+                self.write(dst, result, wide);
+            }
+            Instruction::Test { dst, src } => {
+                let wide = is_wide(dst, src);
+                let result = self.read(dst, wide) & self.read(src, wide);
+                self.set_logic_flags(result, wide);
+            }
+            // inc and dec leave carry alone
+            Instruction::Inc { dst, wide } => {
+                let carry = self.flags.carry;
+                let result = self.add(self.read(dst, *wide), 1, false, *wide);
+                self.flags.carry = carry;
+                self.write(dst, result, *wide);
+            }
+            Instruction::Dec { dst, wide } => {
+                let carry = self.flags.carry;
+                let result = self.sub(self.read(dst, *wide), 1, false, *wide);
+                self.flags.carry = carry;
+                self.write(dst, result, *wide);
+            }
+            Instruction::Not { dst, wide } => {
+                let result = !self.read(dst, *wide);
+                self.write(dst, result, *wide);
+            }
+            Instruction::Neg { dst, wide } => {
+                // Carry ends up set for anything but 0, as 0 - x borrows
+                let result = self.sub(0, self.read(dst, *wide), false, *wide);
+                self.write(dst, result, *wide);
+            }
+            // Displacements are from the next instruction, which ip already points at
+            Instruction::Jump { mnemonic, target } => {
+                // Evaluated for every loop as it counts cx down even when not taken
+                let taken = self.jump_taken(mnemonic);
+                if taken {
+                    self.ip = self.ip.wrapping_add(target.displacement() as u16);
+                }
+            }
+            Instruction::Jmp {
+                target: BranchTarget::Short(target) | BranchTarget::Near(target),
+            } => {
+                self.ip = self.ip.wrapping_add(target.displacement() as u16);
+            }
+            // The offset is the first word of the pointer and the segment the second
+            Instruction::LoadPointer { segment, dst, src } => {
+                let offset = self.read(src, true);
+                let address = self.address(src).unwrap() as u16;
+                let value = self.read(
+                    &Operand::MemDirect {
+                        address: address.wrapping_add(2),
+                        segment: None,
+                    },
+                    true,
+                );
+                self.write(dst, offset, true);
+                self.registers.write_segment(*segment, value);
+            }
+            Instruction::NoOperand { mnemonic } => match *mnemonic {
+                "clc" => self.flags.carry = false,
+                "stc" => self.flags.carry = true,
+                "cmc" => self.flags.carry = !self.flags.carry,
+                "cli" => self.flags.interrupt = false,
+                "sti" => self.flags.interrupt = true,
+                "cld" => self.flags.direction = false,
+                "std" => self.flags.direction = true,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    pub fn dump(&self, w: &mut dyn Write) {
+        writeln!(w, "Final registers:").unwrap();
+        for reg in DUMP_ORDER {
+            let value = self.registers.read16(reg);
+            writeln!(
+                w,
+                "      {}: 0x{value:04x} ({value})",
+                reg.as_ref().to_lowercase()
+            )
+            .unwrap();
+        }
+        for reg in SEGMENT_DUMP_ORDER {
+            let value = self.registers.read_segment(reg);
+            writeln!(
+                w,
+                "      {}: 0x{value:04x} ({value})",
+                reg.as_ref().to_lowercase()
+            )
+            .unwrap();
+        }
+        writeln!(w, "      ip: 0x{:04x} ({})", self.ip, self.ip).unwrap();
+        writeln!(w, "   flags: {}", self.flags).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode;
+
+    // Runs from the first instruction until ip leaves the program or reaches a hlt
+    fn run(bytes: &[u8]) -> Simulator {
+        let instructions = decode(bytes).unwrap();
+        let mut simulator = Simulator::default();
+
+        while let Ok(index) =
+            instructions.binary_search_by_key(&(simulator.ip() as usize), |d| d.offset)
+        {
+            let decoded = &instructions[index];
+            simulator.step(&decoded.instruction, decoded.len as u16);
+            if decoded.instruction.mnemonic() == "hlt" {
+                break;
+            }
+        }
+        simulator
+    }
+
+    #[test]
+    fn byte_registers_alias_the_word_registers() {
+        let mut registers = RegisterFile::default();
+        registers.write8(Register::AH, 0x12);
+        registers.write8(Register::AL, 0x34);
+        assert_eq!(registers.read16(Register::AX), 0x1234);
+
+        registers.write16(Register::DX, 0xabcd);
+        assert_eq!(registers.read8(Register::DH), 0xab);
+        assert_eq!(registers.read8(Register::DL), 0xcd);
+
+        // Writing one half leaves the other alone
+        registers.write8(Register::DL, 0x01);
+        assert_eq!(registers.read16(Register::DX), 0xab01);
+    }
+
+    #[test]
+    fn mov_al_changes_the_low_byte_of_ax() {
+        // mov ax, 0x1234, mov al, 5
+        let simulator = run(&[0xb8, 0x34, 0x12, 0xb0, 0x05]);
+        assert_eq!(simulator.registers.read16(Register::AX), 0x1205);
+    }
+
+    #[test]
+    fn trace_shows_what_each_step_changed() {
+        // mov ax, 5, mov bx, 7, sub ax, bx, hlt
+        let instructions = decode(&[0xb8, 0x05, 0x00, 0xbb, 0x07, 0x00, 0x29, 0xd8, 0xf4]).unwrap();
+        let mut simulator = Simulator::default();
+
+        let trace = instructions
+            .iter()
+            .map(|d| format!("{d} ;{}\n", simulator.step(&d.instruction, d.len as u16)))
+            .collect::<String>();
+
+        assert_eq!(
+            trace,
+            "mov ax, 5 ; ax:0x0->0x5 ip:0x0->0x3\n\
+             mov bx, 7 ; bx:0x0->0x7 ip:0x3->0x6\n\
+             sub ax, bx ; ax:0x5->0xfffe ip:0x6->0x8 flags:->CAS\n\
+             hlt ; ip:0x8->0x9\n"
+        );
+    }
+
+    #[test]
+    fn lds_and_les_load_both_registers() {
+        // mov word [16], 0x1234, mov word [18], 0x5678, mov si, 16, les di, [si], lds bx, [si]
+        let simulator = run(&[
+            0xc7, 0x06, 0x10, 0x00, 0x34, 0x12, 0xc7, 0x06, 0x12, 0x00, 0x78, 0x56, 0xbe, 0x10,
+            0x00, 0xc4, 0x3c, 0xc5, 0x1c,
+        ]);
+
+        assert_eq!(simulator.registers.read16(Register::DI), 0x1234);
+        assert_eq!(
+            simulator.registers.read_segment(SegmentRegister::ES),
+            0x5678
+        );
+        assert_eq!(simulator.registers.read16(Register::BX), 0x1234);
+        assert_eq!(
+            simulator.registers.read_segment(SegmentRegister::DS),
+            0x5678
+        );
+    }
+
+    #[test]
+    fn jne_loops_until_zero() {
+        // mov cx, 3, dec cx, jne back to the dec, hlt
+        let simulator = run(&[0xb9, 0x03, 0x00, 0x49, 0x75, 0xfd, 0xf4]);
+        assert_eq!(simulator.registers.read16(Register::CX), 0);
+        assert_eq!(simulator.ip(), 7);
+    }
+
+    #[test]
+    fn loop_counts_cx_down() {
+        // mov cx, 3, inc bx, loop back to the inc
+        let simulator = run(&[0xb9, 0x03, 0x00, 0x43, 0xe2, 0xfd]);
+        assert_eq!(simulator.registers.read16(Register::BX), 3);
+        assert_eq!(simulator.registers.read16(Register::CX), 0);
+    }
+
+    #[test]
+    fn jmp_skips_ahead() {
+        // jmp over mov ax, 1, mov bx, 2
+        let simulator = run(&[0xeb, 0x03, 0xb8, 0x01, 0x00, 0xbb, 0x02, 0x00]);
+        assert_eq!(simulator.registers.read16(Register::AX), 0);
+        assert_eq!(simulator.registers.read16(Register::BX), 2);
+    }
+
+    #[test]
+    fn signed_and_unsigned_conditions() {
+        // mov ax, -1, cmp ax, 1, jl or jb over mov bx, 1, hlt
+        let program = |jump| {
+            [
+                0xb8, 0xff, 0xff, 0x3d, 0x01, 0x00, jump, 0x03, 0xbb, 0x01, 0x00,
+            ]
+        };
+
+        // -1 is less than 1 signed
+        let simulator = run(&program(0x7c));
+        assert_eq!(simulator.registers.read16(Register::BX), 0);
+
+        // but 0xffff is above 1 unsigned
+        let simulator = run(&program(0x72));
+        assert_eq!(simulator.registers.read16(Register::BX), 1);
+    }
+
+    #[test]
+    fn add_clears_stale_carry() {
+        // stc, add ax, bx
+        let simulator = run(&[0xf9, 0x01, 0xd8]);
+        assert_eq!(simulator.flags.to_string(), "PZ");
+    }
+
+    #[test]
+    fn add_sets_carry_from_the_untruncated_sum() {
+        // mov ax, 0xffff, add ax, 1
+        let simulator = run(&[0xb8, 0xff, 0xff, 0x05, 0x01, 0x00]);
+        assert_eq!(simulator.registers.read16(Register::AX), 0);
+        assert_eq!(simulator.flags.to_string(), "CPAZ");
+    }
+
+    #[test]
+    fn add_sets_overflow_on_a_sign_change() {
+        // mov al, 0x7f, add al, 1
+        let simulator = run(&[0xb0, 0x7f, 0x04, 0x01]);
+        assert_eq!(simulator.registers.read8(Register::AL), 0x80);
+        assert_eq!(simulator.flags.to_string(), "ASO");
+    }
+
+    #[test]
+    fn sub_sets_carry_on_borrow() {
+        // mov ax, 0, sub ax, 1
+        let simulator = run(&[0xb8, 0x00, 0x00, 0x2d, 0x01, 0x00]);
+        assert_eq!(simulator.registers.read16(Register::AX), 0xffff);
+        assert_eq!(simulator.flags.to_string(), "CPAS");
+    }
+
+    #[test]
+    fn sub_of_itself_is_zero() {
+        // mov bx, 0x8005, stc, sub bx, bx
+        let simulator = run(&[0xbb, 0x05, 0x80, 0xf9, 0x29, 0xdb]);
+        assert_eq!(simulator.registers.read16(Register::BX), 0);
+        assert!(simulator.flags.zero);
+        assert!(!simulator.flags.sign);
+        assert!(!simulator.flags.carry);
+        assert_eq!(simulator.flags.to_string(), "PZ");
+    }
+
+    #[test]
+    fn xor_of_itself_is_zero() {
+        // mov ax, 0x7fff, add ax, 1 to set overflow, stc, mov cx, 0x1234, xor cx, cx
+        let simulator = run(&[
+            0xb8, 0xff, 0x7f, 0x05, 0x01, 0x00, 0xf9, 0xb9, 0x34, 0x12, 0x31, 0xc9,
+        ]);
+        assert_eq!(simulator.registers.read16(Register::CX), 0);
+        assert!(simulator.flags.zero);
+        assert!(!simulator.flags.carry);
+        assert!(!simulator.flags.overflow);
+    }
+
+    #[test]
+    fn adc_adds_the_carry() {
+        // mov ax, 0xffff, add ax, 1, mov bx, 2, adc bx, cx
+        let simulator = run(&[
+            0xb8, 0xff, 0xff, 0x05, 0x01, 0x00, 0xbb, 0x02, 0x00, 0x11, 0xcb,
+        ]);
+        assert_eq!(simulator.registers.read16(Register::BX), 3);
+        assert!(!simulator.flags.carry);
+    }
+
+    #[test]
+    fn sbb_subtracts_the_borrow() {
+        // stc, mov bx, 5, sbb bx, cx
+        let simulator = run(&[0xf9, 0xbb, 0x05, 0x00, 0x19, 0xcb]);
+        assert_eq!(simulator.registers.read16(Register::BX), 4);
+
+        // sbb bx, cx with bx = cx = 0 and carry set borrows again
+        let simulator = run(&[0xf9, 0x19, 0xcb]);
+        assert_eq!(simulator.registers.read16(Register::BX), 0xffff);
+        assert!(simulator.flags.carry);
+    }
+
+    #[test]
+    fn logic_clears_carry_and_overflow() {
+        // stc, mov al, 0x7f, add al, 1, and al, al
+        let simulator = run(&[0xf9, 0xb0, 0x7f, 0x04, 0x01, 0x20, 0xc0]);
+        assert!(!simulator.flags.carry);
+        assert!(!simulator.flags.overflow);
+        assert!(simulator.flags.sign);
+    }
+
+    #[test]
+    fn inc_keeps_carry() {
+        // stc, inc ax
+        let simulator = run(&[0xf9, 0x40]);
+        assert_eq!(simulator.registers.read16(Register::AX), 1);
+        assert!(simulator.flags.carry);
+    }
+
+    #[test]
+    fn neg_sets_carry_unless_zero() {
+        // mov ax, 5, neg ax
+        let simulator = run(&[0xb8, 0x05, 0x00, 0xf7, 0xd8]);
+        assert_eq!(simulator.registers.read16(Register::AX), 0xfffb);
+        assert!(simulator.flags.carry);
+
+        // neg ax with ax = 0
+        let simulator = run(&[0xf7, 0xd8]);
+        assert!(!simulator.flags.carry);
+    }
+}
@@ -0,0 +1,248 @@
+use pap86::{
+    decode, encode::encode, EffectiveAddressFormula, Instruction, Operand, Register,
+    SegmentRegister,
+};
+
+// xorshift64, enough to spread the generated operands without pulling in rand
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u8 {
+        (self.next() % n) as u8
+    }
+
+    fn flip(&mut self) -> bool {
+        self.next() & 1 > 0
+    }
+}
+
+fn register(rng: &mut Rng, wide: bool) -> Register {
+    Register::from_repr((wide as u8) << 3 | rng.below(8)).unwrap()
+}
+
+fn segment(rng: &mut Rng) -> SegmentRegister {
+    SegmentRegister::from_repr(rng.below(4)).unwrap()
+}
+
+fn memory(rng: &mut Rng) -> Operand {
+    let segment = rng.flip().then(|| segment(rng));
+
+    if rng.below(8) == 0 {
+        return Operand::MemDirect {
+            address: rng.next() as u16,
+            segment,
+        };
+    }
+
+    Operand::Mem {
+        formula: EffectiveAddressFormula::from_repr(rng.below(8)).unwrap(),
+        displacement: match rng.below(3) {
+            0 => None,
+            1 => Some(rng.next() as i8 as i16),
+            _ => Some(rng.next() as i16),
+        },
+        segment,
+    }
+}
+
+fn immediate(rng: &mut Rng, wide: bool, needs_size: bool) -> Operand {
+    Operand::Immediate {
+        value: if wide {
+            rng.next() as u16
+        } else {
+            rng.next() as u8 as u16
+        },
+        wide,
+        needs_size,
+        signed: true,
+    }
+}
+
+// Any mov the decoder handles, with the operand widths agreeing
+fn mov(rng: &mut Rng) -> Instruction {
+    let wide = rng.flip();
+
+    let (dst, src) = match rng.below(7) {
+        0 => (
+            Operand::Register(register(rng, wide)),
+            Operand::Register(register(rng, wide)),
+        ),
+        1 => (Operand::Register(register(rng, wide)), memory(rng)),
+        2 => (memory(rng), Operand::Register(register(rng, wide))),
+        3 => (
+            Operand::Register(register(rng, wide)),
+            immediate(rng, wide, false),
+        ),
+        4 => (memory(rng), immediate(rng, wide, true)),
+        5 => (
+            Operand::SegmentRegister(segment(rng)),
+            if rng.flip() {
+                Operand::Register(register(rng, true))
+            } else {
+                memory(rng)
+            },
+        ),
+        _ => (
+            if rng.flip() {
+                Operand::Register(register(rng, true))
+            } else {
+                memory(rng)
+            },
+            Operand::SegmentRegister(segment(rng)),
+        ),
+    };
+
+    Instruction::Mov { dst, src }
+}
+
+// An immediate to register, memory or the accumulator for any of the eight arithmetic operations
+fn arithmetic_immediate(rng: &mut Rng) -> Instruction {
+    let wide = rng.flip();
+
+    let dst = match rng.below(3) {
+        0 => Operand::Register(register(rng, wide)),
+        1 => Operand::Register(if wide { Register::AX } else { Register::AL }),
+        _ => memory(rng),
+    };
+    let needs_size = !matches!(dst, Operand::Register(_));
+    let src = match immediate(rng, wide, needs_size) {
+        // Half of the words small enough for the sign extended byte
+        Operand::Immediate { value, .. } if wide && rng.flip() => Operand::Immediate {
+            value: value as u8 as i8 as u16,
+            wide,
+            needs_size,
+            signed: true,
+        },
+        src => src,
+    };
+
+    arithmetic(rng, dst, src)
+}
+
+// A register with a register or memory operand in either direction
+fn arithmetic_reg_mem(rng: &mut Rng) -> Instruction {
+    let wide = rng.flip();
+    let reg = Operand::Register(register(rng, wide));
+
+    let (dst, src) = match rng.below(3) {
+        0 => (reg, Operand::Register(register(rng, wide))),
+        1 => (reg, memory(rng)),
+        _ => (memory(rng), reg),
+    };
+
+    arithmetic(rng, dst, src)
+}
+
+fn arithmetic(rng: &mut Rng, dst: Operand, src: Operand) -> Instruction {
+    match rng.below(8) {
+        0 => Instruction::Add { dst, src },
+        1 => Instruction::Or { dst, src },
+        2 => Instruction::Adc { dst, src },
+        3 => Instruction::Sbb { dst, src },
+        4 => Instruction::And { dst, src },
+        5 => Instruction::Sub { dst, src },
+        6 => Instruction::Xor { dst, src },
+        _ => Instruction::Cmp { dst, src },
+    }
+}
+
+const ITERATIONS: usize = 100_000;
+
+// Encodes random movs, decodes the bytes and checks that encoding the result gives the same bytes
+#[test]
+fn movs_roundtrip() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+    for _ in 0..ITERATIONS {
+        roundtrip(mov(&mut rng));
+    }
+}
+
+fn roundtrip(instruction: Instruction) -> Vec<u8> {
+    let bytes = encode(&[instruction]).unwrap();
+
+    let decoded = decode(&bytes).unwrap_or_else(|e| panic!("{instruction} ({bytes:02x?}): {e}"));
+    assert_eq!(decoded.len(), 1, "{instruction} ({bytes:02x?})");
+
+    let reencoded = encode(&[decoded[0].instruction]).unwrap();
+    assert_eq!(
+        reencoded, bytes,
+        "{instruction} decodes to {}",
+        decoded[0].instruction
+    );
+
+    bytes
+}
+
+#[test]
+fn arithmetic_immediates_roundtrip() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+    for _ in 0..ITERATIONS {
+        roundtrip(arithmetic_immediate(&mut rng));
+    }
+}
+
+#[test]
+fn arithmetic_reg_mem_roundtrip() {
+    let mut rng = Rng(0xbf58_476d_1ce4_e5b9);
+
+    for _ in 0..ITERATIONS {
+        roundtrip(arithmetic_reg_mem(&mut rng));
+    }
+}
+
+// The word forms take the sign extended byte of 0x83 when the value fits it and 0x81 otherwise
+#[test]
+fn word_immediates_pick_the_short_form() {
+    let bx = Operand::Mem {
+        formula: EffectiveAddressFormula::Bx,
+        displacement: None,
+        segment: None,
+    };
+    let word = |value: i16| Operand::Immediate {
+        value: value as u16,
+        wide: true,
+        needs_size: true,
+        signed: true,
+    };
+
+    let cases: [(i16, &[u8]); 5] = [
+        (1000, &[0x81, 0x07, 0xe8, 0x03]),
+        (-5, &[0x83, 0x07, 0xfb]),
+        (127, &[0x83, 0x07, 0x7f]),
+        (128, &[0x81, 0x07, 0x80, 0x00]),
+        (-129, &[0x81, 0x07, 0x7f, 0xff]),
+    ];
+    for (value, bytes) in cases {
+        let add = Instruction::Add {
+            dst: bx,
+            src: word(value),
+        };
+        assert_eq!(roundtrip(add), bytes, "{add}");
+    }
+
+    // The accumulator has its own full word form, but a small value is still shorter as 0x83
+    let ax = Operand::Register(Register::AX);
+    assert_eq!(
+        roundtrip(Instruction::Sub {
+            dst: ax,
+            src: word(1000)
+        }),
+        [0x2d, 0xe8, 0x03]
+    );
+    assert_eq!(
+        roundtrip(Instruction::Sub {
+            dst: ax,
+            src: word(-5)
+        }),
+        [0x83, 0xe8, 0xfb]
+    );
+}
@@ -1,32 +1,192 @@
-use std::{env, fs, process::Command};
+use std::{
+    env::{self, consts::EXE_SUFFIX},
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    thread,
+};
 
-static TEST_CASES: &[&str] = &[
-    "listing_0037_single_register_mov",
-    "listing_0038_many_register_mov",
-    "listing_0039_more_movs",
-    "listing_0040_challenge_movs",
-];
+static LISTINGS_DIR: &str = "perfaware/part1";
 
-fn run_test_case(test: &str) {
-    let input = format!("perfaware/part1/{test}");
-
-    let original = fs::read(&input).unwrap();
+enum Outcome {
+    Passed,
+    Skipped(String),
+    Failed(String),
+}
 
-    assert!(Command::new("cargo")
-        .args(["run", "-p", "pap86", "--", "-o", "target/test.asm", &input])
-        .status()
+// Every assembled listing_* binary that has its .asm source next to it
+fn discover_test_cases() -> Vec<String> {
+    let mut tests = fs::read_dir(LISTINGS_DIR)
         .unwrap()
-        .success());
+        .filter_map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name()?.to_str()?.to_owned();
 
-    assert!(Command::new("tools/nasm")
-        .args(["target/test.asm"])
-        .status()
-        .unwrap()
-        .success());
+            let is_binary = name.starts_with("listing_") && path.extension().is_none();
+            let has_source = path.with_extension("asm").exists();
+
+            (is_binary && has_source).then_some(name)
+        })
+        .collect::<Vec<_>>();
+
+    tests.sort();
+    tests
+}
+
+// NASM from the environment overrides nasm on PATH, which is preferred over the bundled copy
+fn find_nasm() -> Option<PathBuf> {
+    if let Some(nasm) = env::var_os("NASM") {
+        return Some(PathBuf::from(nasm));
+    }
+
+    let name = format!("nasm{EXE_SUFFIX}");
+
+    env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join(&name))
+        .chain([Path::new("tools").join(&name)])
+        .find(|path| path.is_file())
+}
+
+fn pap86() -> String {
+    format!("target/debug/pap86{EXE_SUFFIX}")
+}
+
+// (offset, text) of each instruction in a binary, read back from pap86's --show-bytes output
+fn disassemble(path: &str) -> Option<Vec<(usize, String)>> {
+    let output = Command::new(pap86())
+        .args(["--show-bytes", path])
+        .output()
+        .ok()?;
+
+    let mut offset = 0;
+    let mut instructions = Vec::new();
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        // The byte column never has two spaces in a row, while the padding after it always does
+        let Some((bytes, text)) = line.split_once("  ") else {
+            continue;
+        };
+        instructions.push((offset, text.trim().to_owned()));
+        offset += bytes.split(' ').count();
+    }
+
+    Some(instructions)
+}
+
+fn instruction_at(path: &str, offset: usize) -> String {
+    disassemble(path)
+        .and_then(|instructions| {
+            instructions
+                .into_iter()
+                .take_while(|(start, _)| *start <= offset)
+                .last()
+        })
+        .map(|(start, text)| format!("{text} (at {start})"))
+        .unwrap_or_else(|| "unable to disassemble".to_owned())
+}
+
+// Bytes around offset with the differing one in brackets
+fn hex_window(bytes: &[u8], offset: usize) -> String {
+    let start = offset.saturating_sub(8);
+    let end = (offset + 8).min(bytes.len());
 
-    let new = fs::read("target/test").unwrap();
+    (start..end)
+        .map(|i| {
+            if i == offset {
+                format!("[{:02x}]", bytes[i])
+            } else {
+                format!("{:02x}", bytes[i])
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn describe_difference(input: &str, reassembled: &str, original: &[u8], new: &[u8]) -> String {
+    let offset = original
+        .iter()
+        .zip(new)
+        .position(|(a, b)| a != b)
+        .unwrap_or(original.len().min(new.len()));
+
+    let mut description = if offset < original.len() && offset < new.len() {
+        format!("bytes differ at offset {offset}")
+    } else {
+        format!(
+            "lengths differ, {} bytes originally and {} reassembled",
+            original.len(),
+            new.len()
+        )
+    };
+
+    description += &format!(
+        "\n  original:    {}\n  reassembled: {}",
+        hex_window(original, offset),
+        hex_window(new, offset)
+    );
+    description += &format!(
+        "\n  original:    {}\n  reassembled: {}",
+        instruction_at(input, offset),
+        instruction_at(reassembled, offset)
+    );
 
-    assert_eq!(original, new);
+    description
+}
+
+// pap86's error for an opcode the decoder has no entry for
+fn is_unsupported(stderr: &str) -> bool {
+    stderr.starts_with("Unable to decode instruction") && stderr.contains(": Invalid opcode: ")
+}
+
+// Each case writes to its own files under target so cases can run at the same time
+fn run_test_case(nasm: &Path, test: &str) -> Outcome {
+    let input = Path::new(LISTINGS_DIR).join(test);
+    let input = input.to_str().unwrap();
+    let asm = format!("target/{test}.asm");
+    let reassembled = format!("target/{test}");
+
+    let original = fs::read(input).unwrap();
+
+    let disassembled = Command::new(pap86())
+        .args(["-o", &asm, input])
+        .output()
+        .unwrap();
+
+    // Listings using instructions the decoder doesn't handle yet aren't failures. Any other
+    // error, or a panic, is
+    if !disassembled.status.success() {
+        let stderr = String::from_utf8_lossy(&disassembled.stderr)
+            .trim()
+            .to_owned();
+
+        return if disassembled.status.code() == Some(1) && is_unsupported(&stderr) {
+            Outcome::Skipped(stderr)
+        } else {
+            Outcome::Failed(format!("pap86 failed: {stderr}"))
+        };
+    }
+
+    let assembled = Command::new(nasm)
+        .args([&asm, "-o", &reassembled])
+        .output()
+        .unwrap();
+
+    if !assembled.status.success() {
+        return Outcome::Failed(format!(
+            "nasm failed: {}",
+            String::from_utf8_lossy(&assembled.stderr).trim()
+        ));
+    }
+
+    let new = fs::read(&reassembled).unwrap();
+
+    if original == new {
+        Outcome::Passed
+    } else {
+        Outcome::Failed(describe_difference(input, &reassembled, &original, &new))
+    }
 }
 
 fn main() {
@@ -34,13 +194,57 @@ fn main() {
         env::set_current_dir("../").unwrap();
     }
 
+    let Some(nasm) = find_nasm() else {
+        println!(
+            "Skipping the roundtrip tests: nasm isn't on PATH or in tools, set NASM to its path"
+        );
+        return;
+    };
+
     assert!(Command::new("cargo")
         .args(["build", "-p", "pap86"])
         .status()
         .unwrap()
         .success());
 
-    for test in TEST_CASES {
-        run_test_case(test);
+    let tests = discover_test_cases();
+
+    let outcomes = thread::scope(|scope| {
+        let handles = tests
+            .iter()
+            .map(|test| scope.spawn(|| run_test_case(&nasm, test)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    let width = tests.iter().map(|test| test.len()).max().unwrap_or(0);
+    let (mut passed, mut skipped, mut failed) = (0, 0, 0);
+
+    for (test, outcome) in tests.iter().zip(&outcomes) {
+        match outcome {
+            Outcome::Passed => {
+                passed += 1;
+                println!("{test:width$}  PASS");
+            }
+            Outcome::Skipped(reason) => {
+                skipped += 1;
+                println!("{test:width$}  SKIP  {reason}");
+            }
+            Outcome::Failed(reason) => {
+                failed += 1;
+                println!("{test:width$}  FAIL  {reason}");
+            }
+        }
+    }
+
+    println!();
+    println!("{passed} passed, {skipped} skipped, {failed} failed");
+
+    if failed > 0 {
+        process::exit(1);
     }
 }